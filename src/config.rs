@@ -12,8 +12,10 @@
  * Last Modified: 2026-02-15 14:37:31
  */
 
-use config::{Config, ConfigError, File};
-use serde::Deserialize;
+use config::{Config, ConfigError, Environment, File, Map, Source, Value};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
@@ -21,26 +23,208 @@ pub struct AppConfig {
     pub sender: SenderConfig,
     #[serde(default)]
     pub notifications: Vec<NotificationConfig>,
-    pub forward_to: String,
+    /// Default destination address(es) for receivers without their own `forward_to`.
+    #[serde(default, deserialize_with = "deserialize_addresses")]
+    pub forward_to: Vec<String>,
     pub log_file: Option<String>,
     pub log_level: Option<String>,
     #[serde(default)]
     pub quiet: bool,
+    /// Directory where per-receiver state (e.g. already forwarded IDs) is persisted.
+    /// If unset, state is kept in memory only and lost on restart.
+    pub state_dir: Option<String>,
+    /// Routing rules, evaluated in order against the headers of every email.
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    /// Persistent outbound queue. Without it, emails are sent directly and a failed
+    /// send is only retried if the email is fetched again.
+    pub spool: Option<SpoolConfig>,
+    /// How long tasks may take to finish the emails in hand after a shutdown signal
+    /// before the process exits anyway.
+    pub drain_timeout_seconds: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct SpoolConfig {
+    pub dir: String, // Queued emails live here, given up ones in "<dir>/dead"
+    pub max_attempts: Option<u32>, // Default 10
+    pub max_age_hours: Option<u64>, // Give up on emails queued longer than this
+    pub retry_initial_seconds: Option<u64>, // First retry delay, doubled after every failure, default 60
+    pub retry_max_seconds: Option<u64>,     // Upper bound of the retry delay, default 3600
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
 pub struct ReceiverConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    pub password_file: Option<String>, // Read the password from this file instead, e.g. a Docker secret
+    pub password_command: Option<SecretCommand>, // Or get it from this command when connecting
     #[serde(default = "default_protocol")]
     pub protocol: String, // "pop3" or "imap"
     pub use_tls: Option<bool>,
+    pub tls_mode: Option<TlsMode>, // "implicit", "starttls" or "none"; overrides use_tls
     pub check_interval_seconds: Option<u64>,
     pub delete_after_forward: Option<bool>,
     #[serde(default = "default_imap_folder")]
     pub imap_folder: String, // IMAP mailbox folder, default "INBOX"
+    pub seen_ids_max_count: Option<usize>, // Cap on tracked seen IDs if the server cannot list IDs
+    pub seen_ids_max_age_days: Option<u64>, // Same, forget seen IDs older than this many days
+    pub idle: Option<bool>, // IMAP only: keep a session open and wait for new mail with IDLE
+    pub proxy: Option<String>, // Optional SOCKS5 proxy, e.g. "127.0.0.1:1080"
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    /// Destination address(es) for this receiver; falls back to the global `forward_to`.
+    #[serde(default, deserialize_with = "deserialize_optional_addresses")]
+    pub forward_to: Option<Vec<String>>,
+    /// If set, only emails matching at least one of these filters are forwarded.
+    #[serde(default)]
+    pub include: Vec<FilterConfig>,
+    /// Emails matching any of these filters are not forwarded.
+    #[serde(default)]
+    pub exclude: Vec<FilterConfig>,
+    pub filter_action: Option<FilterAction>, // What to do with filtered emails, default "leave"
+}
+
+/// A message filter. All conditions that are set must match.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct FilterConfig {
+    pub from: Option<String>,         // Glob on the From address
+    pub to: Option<String>,           // Glob on any To or Cc address
+    pub subject: Option<String>,      // Regular expression on the Subject
+    pub list_id: Option<String>, // Glob on the List-Id identifier, "*" matches any mailing list
+    pub auto_submitted: Option<bool>, // Auto-Submitted other than "no", or Precedence bulk/junk/list
+    pub min_size: Option<usize>,      // Raw message size in bytes
+    pub max_size: Option<usize>,
+    pub has_attachments: Option<bool>,
+}
+
+/// What happens at the source to an email that was filtered out.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Leave the email untouched on the server.
+    #[default]
+    Leave,
+    /// Flag the email as seen (IMAP only, same as `Leave` for POP3).
+    MarkSeen,
+    /// Delete the email from the server.
+    Delete,
+}
+
+/// How a receiver connection is secured.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// TLS from the first byte, e.g. IMAPS on port 993.
+    Implicit,
+    /// Plaintext connection upgraded with STARTTLS before logging in, e.g. port 143.
+    Starttls,
+    /// No encryption at all.
+    None,
+}
+
+impl ReceiverConfig {
+    pub fn proxy(&self) -> Option<ProxyConfig> {
+        ProxyConfig::from_fields(&self.proxy, &self.proxy_username, &self.proxy_password)
+    }
+
+    /// Resolves the destination addresses, falling back to the global `forward_to`.
+    pub fn forward_to(&self, default: &[String]) -> Vec<String> {
+        self.forward_to.clone().unwrap_or_else(|| default.to_vec())
+    }
+
+    /// Resolves the TLS mode, falling back to the legacy `use_tls` flag.
+    pub fn tls_mode(&self) -> TlsMode {
+        match (self.tls_mode, self.use_tls) {
+            (Some(mode), _) => mode,
+            (None, Some(false)) => TlsMode::None,
+            (None, _) => TlsMode::Implicit,
+        }
+    }
+}
+
+/// Accepts either a single address or a list of addresses.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(address) => vec![address],
+            OneOrMany::Many(addresses) => addresses,
+        }
+    }
+}
+
+/// A command printing a secret, e.g. from a vault CLI or a keyring. Its output is
+/// cached for `ttl_seconds`, by default 5 minutes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretCommand {
+    pub command: Vec<String>,
+    pub ttl_seconds: Option<u64>,
+}
+
+/// Accepts either just the command or a table with the command and its TTL.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecretCommandForm {
+    Command(Vec<String>),
+    Table {
+        command: Vec<String>,
+        ttl_seconds: Option<u64>,
+    },
+}
+
+impl<'de> Deserialize<'de> for SecretCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match SecretCommandForm::deserialize(deserializer)? {
+            SecretCommandForm::Command(command) => Self {
+                command,
+                ttl_seconds: None,
+            },
+            SecretCommandForm::Table {
+                command,
+                ttl_seconds,
+            } => Self {
+                command,
+                ttl_seconds,
+            },
+        })
+    }
+}
+
+impl SecretCommand {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(
+            self.ttl_seconds
+                .unwrap_or(DEFAULT_SECRET_COMMAND_TTL_SECONDS),
+        )
+    }
+}
+
+impl fmt::Display for SecretCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.command.join(" "))
+    }
+}
+
+fn deserialize_addresses<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    OneOrMany::deserialize(deserializer).map(Into::into)
+}
+
+fn deserialize_optional_addresses<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    Option::<OneOrMany>::deserialize(deserializer).map(|value| value.map(Into::into))
 }
 
 // Default protocol is "pop3"
@@ -53,20 +237,184 @@ fn default_imap_folder() -> String {
     "INBOX".to_string()
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// Default DKIM signed headers, those mail clients display or use for threading
+pub fn default_dkim_headers() -> Vec<String> {
+    [
+        "From",
+        "Reply-To",
+        "Subject",
+        "Date",
+        "To",
+        "Cc",
+        "Message-ID",
+        "In-Reply-To",
+        "References",
+        "MIME-Version",
+        "Content-Type",
+        "Content-Transfer-Encoding",
+    ]
+    .iter()
+    .map(|name| name.to_string())
+    .collect()
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct SenderConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    pub password_file: Option<String>, // Read the password from this file instead, e.g. a Docker secret
+    pub password_command: Option<SecretCommand>, // Or get it from this command when connecting
     pub use_tls: Option<bool>,
+    pub tls_mode: Option<SmtpTlsMode>, // "implicit", "starttls", "opportunistic" or "none"; overrides use_tls
+    pub proxy: Option<String>,         // Optional SOCKS5 proxy, e.g. "127.0.0.1:1080"
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    pub envelope_from: Option<String>, // MAIL FROM address, defaults to username
+    pub srs_secret: Option<String>,    // Enables SRS rewriting of the original sender with this key
+    pub srs_domain: Option<String>,    // Domain of SRS addresses, defaults to that of envelope_from
+    pub header_mode: Option<HeaderMode>, // "x-headers" (default), "resent" (needs envelope_from) or "none"
+    pub forward_mode: Option<ForwardMode>, // "raw" (default) or "attach"
+    pub dkim: Option<DkimConfig>,        // Optional DKIM signing of forwarded emails
+    pub arc: Option<ArcConfig>,          // Optional ARC sealing of forwarded emails
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DkimConfig {
+    pub domain: String,
+    pub selector: String,
+    pub private_key_file: String, // PEM file with an RSA (PKCS#1 or PKCS#8) or Ed25519 (PKCS#8) key
+    #[serde(default = "default_dkim_headers")]
+    pub headers: Vec<String>, // Header fields to sign
+}
+
+/// ARC sealing; unset signing fields are taken from `[sender.dkim]`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ArcConfig {
+    pub domain: Option<String>,
+    pub selector: Option<String>,
+    pub private_key_file: Option<String>,
+    pub headers: Option<Vec<String>>, // Header fields the ARC-Message-Signature covers
+    pub authserv_id: Option<String>,  // Our authentication service ID, defaults to the domain
+    // Authentication service ID(s) of the source mailbox's server, whose results are copied
+    #[serde(default, deserialize_with = "deserialize_addresses")]
+    pub trusted_authserv_id: Vec<String>,
+}
+
+/// How the sender forwards an email.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardMode {
+    /// Re-inject the original message, with the trace headers of `header_mode`.
+    #[default]
+    Raw,
+    /// Send a new message from our own address with the original attached as
+    /// `message/rfc822`, for destinations enforcing DMARC on the original From.
+    Attach,
+}
+
+/// Which trace headers the sender adds to forwarded emails.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeaderMode {
+    /// `X-Forwarded-By`, `X-Original-Message-ID` and `X-Forwarded-Time`.
+    #[default]
+    XHeaders,
+    /// A `Resent-*` block as described in RFC 5322 section 3.6.6.
+    Resent,
+    /// Forward the message byte for byte.
+    None,
+}
+
+/// How the SMTP sender connection is secured.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTlsMode {
+    /// TLS from the first byte, e.g. port 465.
+    Implicit,
+    /// Plaintext connection that must be upgraded with STARTTLS, e.g. port 587.
+    Starttls,
+    /// Upgrade with STARTTLS if the server offers it, otherwise stay in plaintext.
+    Opportunistic,
+    /// No encryption at all.
+    None,
+}
+
+/// A SOCKS5 proxy to tunnel connections through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    fn from_fields(
+        address: &Option<String>,
+        username: &Option<String>,
+        password: &Option<String>,
+    ) -> Option<Self> {
+        address.as_ref().map(|address| Self {
+            address: address.clone(),
+            username: username.clone(),
+            password: password.clone(),
+        })
+    }
+
+    /// Returns the username and password if the proxy requires authentication.
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        self.username
+            .as_deref()
+            .map(|username| (username, self.password.as_deref().unwrap_or_default()))
+    }
+}
+
+impl SenderConfig {
+    pub fn proxy(&self) -> Option<ProxyConfig> {
+        ProxyConfig::from_fields(&self.proxy, &self.proxy_username, &self.proxy_password)
+    }
+
+    /// Resolves the TLS mode: an explicit `tls_mode` wins, `use_tls = false` means
+    /// no encryption, otherwise the submission ports 587 and 25 use STARTTLS and
+    /// everything else implicit TLS.
+    pub fn tls_mode(&self) -> SmtpTlsMode {
+        match (self.tls_mode, self.use_tls) {
+            (Some(mode), _) => mode,
+            (None, Some(false)) => SmtpTlsMode::None,
+            (None, _) if matches!(self.port, 587 | 25) => SmtpTlsMode::Starttls,
+            (None, _) => SmtpTlsMode::Implicit,
+        }
+    }
+
+    /// The MAIL FROM address, `envelope_from` or else the login username.
+    pub fn envelope_from(&self) -> &str {
+        self.envelope_from.as_deref().unwrap_or(&self.username)
+    }
+
+    /// The domain SRS addresses are created in, `srs_domain` or else the domain
+    /// of the envelope sender.
+    pub fn srs_domain(&self) -> Option<&str> {
+        self.srs_domain.as_deref().or_else(|| {
+            self.envelope_from()
+                .rsplit_once('@')
+                .map(|(_, domain)| domain)
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum NotificationConfig {
     #[serde(rename = "telegram")]
-    Telegram { chat_id: String, token: String },
+    Telegram {
+        chat_id: String,
+        #[serde(default)]
+        token: String,
+        token_file: Option<String>, // Read the bot token from this file instead
+        token_command: Option<SecretCommand>, // Or get it from this command when sending
+    },
     #[serde(rename = "file")]
     File { file_path: String },
     #[serde(rename = "email")]
@@ -74,34 +422,139 @@ pub enum NotificationConfig {
         smtp_host: String,
         smtp_port: u16,
         smtp_username: String,
+        #[serde(default)]
         smtp_password: String,
+        smtp_password_file: Option<String>, // Read the password from this file instead
+        smtp_password_command: Option<SecretCommand>, // Or get it from this command when sending
     },
 }
 
+/// A routing rule. All conditions that are set must match; a rule without
+/// conditions matches every email.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RuleConfig {
+    pub from: Option<String>, // Glob on the From address, e.g. "*@billing.vendor.com"
+    pub to: Option<String>,   // Glob on any To or Cc address
+    pub subject: Option<String>, // Case-insensitive substring of the Subject
+    #[serde(default, deserialize_with = "deserialize_optional_addresses")]
+    pub forward_to: Option<Vec<String>>, // Destination(s) added when the rule matches
+    #[serde(default)]
+    pub skip: bool, // Do not forward matching emails at all
+    #[serde(default)]
+    pub stop: bool, // Stop evaluating further rules after a match
+}
+
+// Default spool retry settings
+pub const DEFAULT_SPOOL_MAX_ATTEMPTS: u32 = 10;
+pub const DEFAULT_SPOOL_RETRY_INITIAL_SECONDS: u64 = 60;
+pub const DEFAULT_SPOOL_RETRY_MAX_SECONDS: u64 = 60 * 60;
+
+// How often the spool is scanned for emails that are due for a retry
+pub const SPOOL_CHECK_INTERVAL_SECONDS: u64 = 15;
+
 // Default check interval in seconds (5 minutes)
 pub const DEFAULT_CHECK_INTERVAL_SECONDS: u64 = 300;
 
+// Maximum time to stay in IMAP IDLE before re-issuing it; servers may drop
+// clients idling for more than 29 minutes (RFC 2177)
+pub const IDLE_TIMEOUT_SECONDS: u64 = 25 * 60;
+
+// How long `test-connection` waits for each account before giving up on it
+pub const CONNECTION_TEST_TIMEOUT_SECONDS: u64 = 30;
+
+// Default time to drain in-flight emails on shutdown, below the 10 second
+// grace period `docker stop` gives before killing the container
+pub const DEFAULT_DRAIN_TIMEOUT_SECONDS: u64 = 8;
+
+// How often `--watch-config` checks the config file for changes
+pub const CONFIG_WATCH_INTERVAL_SECONDS: u64 = 5;
+
+// How long the output of a `*_command` secret is reused before running it again
+pub const DEFAULT_SECRET_COMMAND_TTL_SECONDS: u64 = 5 * 60;
+
+// How long one SMTP session, from connecting to QUIT, may take
+pub const SMTP_TIMEOUT_SECONDS: u64 = 60;
+
+// Prefix of environment variables overriding config keys, e.g. MAILFWD__SENDER__PASSWORD
+pub const ENV_PREFIX: &str = "MAILFWD";
+
 // Default config file path
 pub const DEFAULT_CONFIG_PATH: &str = "/etc/mail-forwarder/config.toml";
 
 impl AppConfig {
     /// Load config from defaults, then file (if exists), then environment variables
     pub fn new() -> Result<Self, ConfigError> {
-        Config::builder()
-            .add_source(File::with_name(DEFAULT_CONFIG_PATH).required(false))
-            .build()?
-            .try_deserialize()
+        Self::source(None)?.try_deserialize()
     }
 
     /// Load config from a specific file path
     pub fn new_from_file(path: &str) -> Result<Self, ConfigError> {
+        Self::source(Some(path))?.try_deserialize()
+    }
+
+    /// Reads the configuration sources without deserializing them: the given
+    /// file, which must exist, or else the optional default one, overridden by
+    /// `MAILFWD__...` environment variables.
+    pub fn source(path: Option<&str>) -> Result<Config, ConfigError> {
+        Self::source_with_env(path, environment())
+    }
+
+    fn source_with_env(path: Option<&str>, env: Environment) -> Result<Config, ConfigError> {
+        let file = match path {
+            Some(path) => File::with_name(path).required(true),
+            None => File::with_name(DEFAULT_CONFIG_PATH).required(false),
+        };
         Config::builder()
-            .add_source(File::with_name(path).required(true))
-            .build()?
-            .try_deserialize()
+            .add_source(file)
+            .add_source(IndexedEnvironment(env))
+            .build()
     }
 }
 
+/// Environment variables named after the config key, upper case with `__` between
+/// the parts, e.g. `MAILFWD__SENDER__PASSWORD` for `sender.password`.
+fn environment() -> Environment {
+    Environment::with_prefix(ENV_PREFIX)
+        .prefix_separator("__")
+        .separator("__")
+}
+
+/// Turns numeric parts of environment variable keys into array indexes, so
+/// `MAILFWD__RECEIVERS__0__PASSWORD` sets `receivers[0].password` rather than
+/// replacing the receivers with a table.
+#[derive(Debug, Clone)]
+struct IndexedEnvironment(Environment);
+
+impl Source for IndexedEnvironment {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        Ok(self
+            .0
+            .collect()?
+            .into_iter()
+            .map(|(key, value)| (index_arrays(&key), value))
+            .collect())
+    }
+}
+
+fn index_arrays(key: &str) -> String {
+    let mut path = String::new();
+    for part in key.split('.') {
+        if part.parse::<usize>().is_ok() && !path.is_empty() {
+            path.push_str(&format!("[{}]", part));
+        } else {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(part);
+        }
+    }
+    path
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,7 +588,7 @@ mod tests {
             .try_deserialize()
             .unwrap();
 
-        assert_eq!(config.forward_to, "target@example.com");
+        assert_eq!(config.forward_to, vec!["target@example.com".to_string()]);
 
         assert_eq!(config.sender.host, "smtp.example.com");
         assert_eq!(config.sender.port, 587);
@@ -214,6 +667,193 @@ mod tests {
         assert_eq!(config.receivers[1].host, "r2");
     }
 
+    #[test]
+    fn test_sender_tls_mode() {
+        let sender =
+            |port: u16, use_tls: Option<bool>, tls_mode: Option<SmtpTlsMode>| SenderConfig {
+                port,
+                use_tls,
+                tls_mode,
+                ..Default::default()
+            };
+
+        assert_eq!(sender(465, None, None).tls_mode(), SmtpTlsMode::Implicit);
+        assert_eq!(sender(587, None, None).tls_mode(), SmtpTlsMode::Starttls);
+        assert_eq!(
+            sender(587, Some(true), None).tls_mode(),
+            SmtpTlsMode::Starttls
+        );
+        assert_eq!(sender(25, None, None).tls_mode(), SmtpTlsMode::Starttls);
+        assert_eq!(sender(587, Some(false), None).tls_mode(), SmtpTlsMode::None);
+        assert_eq!(
+            sender(587, Some(false), Some(SmtpTlsMode::Opportunistic)).tls_mode(),
+            SmtpTlsMode::Opportunistic
+        );
+
+        let config: AppConfig = Config::builder()
+            .add_source(File::from_str(
+                r#"
+                receivers = []
+
+                [sender]
+                host = "h"
+                port = 2525
+                username = "u"
+                password = "p"
+                tls_mode = "opportunistic"
+                header_mode = "x-headers"
+                "#,
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(config.sender.tls_mode(), SmtpTlsMode::Opportunistic);
+        assert_eq!(config.sender.header_mode, Some(HeaderMode::XHeaders));
+    }
+
+    #[test]
+    fn test_receiver_tls_mode() {
+        let toml_str = r#"
+            forward_to = "t"
+            [sender]
+            host = "h"
+            port = 1
+            username = "u"
+            password = "p"
+
+            [[receivers]]
+            host = "r1"
+            port = 993
+            username = "u1"
+            password = "p1"
+
+            [[receivers]]
+            host = "r2"
+            port = 143
+            username = "u2"
+            password = "p2"
+            use_tls = false
+
+            [[receivers]]
+            host = "r3"
+            port = 143
+            username = "u3"
+            password = "p3"
+            use_tls = true
+            tls_mode = "starttls"
+        "#;
+
+        let config: AppConfig = Config::builder()
+            .add_source(File::from_str(toml_str, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(config.receivers[0].tls_mode(), TlsMode::Implicit);
+        assert_eq!(config.receivers[1].tls_mode(), TlsMode::None);
+        assert_eq!(config.receivers[2].tls_mode(), TlsMode::Starttls);
+    }
+
+    #[test]
+    fn test_per_receiver_forward_to() {
+        let toml_str = r#"
+            forward_to = ["a@example.com", "b@example.com"]
+
+            [sender]
+            host = "h"
+            port = 1
+            username = "u"
+            password = "p"
+
+            [[receivers]]
+            host = "r1"
+            port = 1
+            username = "u1"
+            password = "p1"
+
+            [[receivers]]
+            host = "r2"
+            port = 2
+            username = "u2"
+            password = "p2"
+            forward_to = "team@example.com"
+
+            [[receivers]]
+            host = "r3"
+            port = 3
+            username = "u3"
+            password = "p3"
+            forward_to = ["x@example.com", "y@example.com"]
+        "#;
+
+        let config: AppConfig = Config::builder()
+            .add_source(File::from_str(toml_str, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!(
+            config.receivers[0].forward_to(&config.forward_to),
+            vec!["a@example.com", "b@example.com"]
+        );
+        assert_eq!(
+            config.receivers[1].forward_to(&config.forward_to),
+            vec!["team@example.com"]
+        );
+        assert_eq!(
+            config.receivers[2].forward_to(&config.forward_to),
+            vec!["x@example.com", "y@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_receiver_filters() {
+        let toml_str = r#"
+            forward_to = "t@example.com"
+
+            [sender]
+            host = "h"
+            port = 1
+            username = "u"
+            password = "p"
+
+            [[receivers]]
+            host = "r1"
+            port = 1
+            username = "u1"
+            password = "p1"
+            filter_action = "mark_seen"
+            exclude = [
+                { list_id = "*" },
+                { auto_submitted = true },
+                { subject = "(?i)^out of office", max_size = 4096 },
+            ]
+
+            [[receivers.include]]
+            from = "*@example.com"
+            has_attachments = true
+        "#;
+
+        let config: AppConfig = Config::builder()
+            .add_source(File::from_str(toml_str, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let receiver = &config.receivers[0];
+        assert_eq!(receiver.filter_action, Some(FilterAction::MarkSeen));
+        assert_eq!(receiver.include.len(), 1);
+        assert_eq!(receiver.include[0].has_attachments, Some(true));
+        assert_eq!(receiver.exclude.len(), 3);
+        assert_eq!(receiver.exclude[0].list_id.as_deref(), Some("*"));
+        assert_eq!(receiver.exclude[2].max_size, Some(4096));
+    }
+
     #[test]
     fn test_invalid_config_type() {
         let toml_str = r#"
@@ -233,4 +873,48 @@ mod tests {
             .try_deserialize();
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let file: AppConfig =
+            AppConfig::source_with_env(Some("config.toml"), Environment::default())
+                .unwrap()
+                .try_deserialize()
+                .unwrap();
+        let vars = [
+            ("MAILFWD__SENDER__PASSWORD", "from-env"),
+            ("MAILFWD__SENDER__PORT", "2525"),
+            ("MAILFWD__RECEIVERS__1__PASSWORD", "receiver-env"),
+            ("MAILFWD__FORWARD_TO", "env@example.com"),
+            ("APP_SENDER__PASSWORD", "ignored"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let config: AppConfig =
+            AppConfig::source_with_env(Some("config.toml"), environment().source(Some(vars)))
+                .unwrap()
+                .try_deserialize()
+                .unwrap();
+
+        assert_eq!(config.sender.password, "from-env");
+        assert_eq!(config.sender.port, 2525);
+        assert_eq!(config.sender.username, file.sender.username);
+        assert_eq!(config.receivers.len(), file.receivers.len());
+        assert_eq!(config.receivers[0].password, file.receivers[0].password);
+        assert_eq!(config.receivers[1].password, "receiver-env");
+        assert_eq!(config.receivers[1].host, file.receivers[1].host);
+        assert_eq!(config.forward_to, vec!["env@example.com"]);
+    }
+
+    #[test]
+    fn test_index_arrays() {
+        assert_eq!(index_arrays("sender.password"), "sender.password");
+        assert_eq!(
+            index_arrays("receivers.0.include.2.subject"),
+            "receivers[0].include[2].subject"
+        );
+        assert_eq!(index_arrays("0.password"), "0.password");
+    }
 }
@@ -0,0 +1,379 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: connection_test.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 21:40:37
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 21:40:37
+ */
+
+use crate::config::{
+    AppConfig, CONNECTION_TEST_TIMEOUT_SECONDS, ReceiverConfig, SenderConfig, SmtpTlsMode, TlsMode,
+};
+use crate::imap_receiver::ImapReceiver;
+use crate::pop3_receiver::Pop3Receiver;
+use crate::smtp_sender;
+use crate::traits::{ConnectError, ConnectStage, MailReceiver, MailboxStatus};
+use futures::future::join_all;
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+/// The outcome of one step of opening a session with an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Ok,
+    Failed,
+    /// Not attempted because an earlier step failed.
+    Skipped,
+    /// Not part of the session, e.g. TLS with `tls_mode = "none"`.
+    NotUsed,
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Failed => "FAILED",
+            StepStatus::Skipped => "skipped",
+            StepStatus::NotUsed => "not used",
+        })
+    }
+}
+
+/// How logging in to one receiver or the sender went.
+#[derive(Debug, Serialize)]
+pub struct AccountReport {
+    /// The TOML key of the account, e.g. "receivers[0]" or "sender".
+    pub account: String,
+    pub protocol: String,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub tls_mode: &'static str,
+    pub connection: StepStatus,
+    pub tls: StepStatus,
+    pub auth: StepStatus,
+    /// Opening the mailbox and counting its messages, receivers only.
+    pub mailbox: StepStatus,
+    pub messages: Option<usize>,
+    /// Messages not flagged as seen, IMAP only.
+    pub unseen: Option<usize>,
+    pub error: Option<String>,
+}
+
+impl AccountReport {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Fills in the steps from the outcome of a check. `encrypted` tells whether
+    /// the session uses TLS, or may when it failed before getting that far.
+    fn record(
+        &mut self,
+        result: anyhow::Result<Option<MailboxStatus>>,
+        encrypted: bool,
+        has_mailbox: bool,
+    ) {
+        let failed = match &result {
+            Ok(_) => None,
+            Err(e) => Some(ConnectError::stage_of(e)),
+        };
+        let step = |stage: ConnectStage| match failed {
+            Some(failed) if stage == failed => StepStatus::Failed,
+            Some(failed) if stage > failed => StepStatus::Skipped,
+            _ => StepStatus::Ok,
+        };
+
+        self.connection = step(ConnectStage::Connect);
+        self.tls = if encrypted || failed == Some(ConnectStage::Tls) {
+            step(ConnectStage::Tls)
+        } else {
+            StepStatus::NotUsed
+        };
+        self.auth = step(ConnectStage::Auth);
+        self.mailbox = if has_mailbox {
+            step(ConnectStage::Mailbox)
+        } else {
+            StepStatus::NotUsed
+        };
+
+        match result {
+            Ok(status) => {
+                self.messages = status.map(|status| status.messages);
+                self.unseen = status.and_then(|status| status.unseen);
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AccountReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} {} on {}:{}, tls_mode {}): connection {}, tls {}, auth {}",
+            self.account,
+            self.protocol,
+            self.username,
+            self.host,
+            self.port,
+            self.tls_mode,
+            self.connection,
+            self.tls,
+            self.auth
+        )?;
+        if self.mailbox != StepStatus::NotUsed {
+            write!(f, ", mailbox {}", self.mailbox)?;
+        }
+        if let Some(messages) = self.messages {
+            write!(f, ", {} message(s)", messages)?;
+        }
+        if let Some(unseen) = self.unseen {
+            write!(f, ", {} unseen", unseen)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, "\n    {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs in to every receiver and the sender concurrently, without fetching,
+/// sending or changing anything, and reports on each in config order.
+pub async fn run(config: &AppConfig) -> Vec<AccountReport> {
+    let receivers = join_all(
+        config
+            .receivers
+            .iter()
+            .enumerate()
+            .map(|(index, receiver)| check_receiver(index, receiver)),
+    );
+    let (mut reports, sender) = tokio::join!(receivers, check_sender(&config.sender));
+
+    reports.push(sender);
+    reports
+}
+
+async fn check_receiver(index: usize, config: &ReceiverConfig) -> AccountReport {
+    let tls_mode = config.tls_mode();
+    let mut report = new_report(
+        format!("receivers[{}]", index),
+        &config.protocol,
+        &config.host,
+        config.port,
+        &config.username,
+        match tls_mode {
+            TlsMode::Implicit => "implicit",
+            TlsMode::Starttls => "starttls",
+            TlsMode::None => "none",
+        },
+    );
+
+    let mut receiver: Box<dyn MailReceiver> = match config.protocol.as_str() {
+        "imap" => Box::new(ImapReceiver::new(config.clone())),
+        "pop3" => Box::new(Pop3Receiver::new(config.clone())),
+        other => {
+            report.error = Some(format!("Unknown protocol: {}", other));
+            return report;
+        }
+    };
+
+    let result = with_timeout(receiver.check_connection()).await;
+    report.record(result.map(Some), tls_mode != TlsMode::None, true);
+    report
+}
+
+async fn check_sender(config: &SenderConfig) -> AccountReport {
+    let tls_mode = config.tls_mode();
+    let mut report = new_report(
+        "sender".to_string(),
+        "smtp",
+        &config.host,
+        config.port,
+        &config.username,
+        match tls_mode {
+            SmtpTlsMode::Implicit => "implicit",
+            SmtpTlsMode::Starttls => "starttls",
+            SmtpTlsMode::Opportunistic => "opportunistic",
+            SmtpTlsMode::None => "none",
+        },
+    );
+
+    match with_timeout(async { Ok(smtp_sender::test_connection(config).await?) }).await {
+        Ok(encrypted) => report.record(Ok(None), encrypted, false),
+        Err(e) => report.record(Err(e), tls_mode != SmtpTlsMode::None, false),
+    }
+    report
+}
+
+fn new_report(
+    account: String,
+    protocol: &str,
+    host: &str,
+    port: u16,
+    username: &str,
+    tls_mode: &'static str,
+) -> AccountReport {
+    AccountReport {
+        account,
+        protocol: protocol.to_string(),
+        host: host.to_string(),
+        port,
+        username: username.to_string(),
+        tls_mode,
+        connection: StepStatus::Skipped,
+        tls: StepStatus::Skipped,
+        auth: StepStatus::Skipped,
+        mailbox: StepStatus::Skipped,
+        messages: None,
+        unseen: None,
+        error: None,
+    }
+}
+
+/// Gives up on servers that accept the connection but never answer; a timeout
+/// counts against the connection step.
+async fn with_timeout<T>(check: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    let timeout = Duration::from_secs(CONNECTION_TEST_TIMEOUT_SECONDS);
+    tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| {
+            Err(anyhow::anyhow!(
+                "Timed out after {} seconds",
+                CONNECTION_TEST_TIMEOUT_SECONDS
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+
+    fn imap_report() -> AccountReport {
+        new_report(
+            "receivers[0]".to_string(),
+            "imap",
+            "imap.example.com",
+            993,
+            "user@example.com",
+            "implicit",
+        )
+    }
+
+    #[test]
+    fn test_record_success() {
+        let mut report = imap_report();
+        let status = MailboxStatus {
+            messages: 3,
+            unseen: Some(1),
+        };
+        report.record(Ok(Some(status)), true, true);
+
+        assert!(report.succeeded());
+        assert_eq!(
+            [report.connection, report.tls, report.auth, report.mailbox],
+            [StepStatus::Ok; 4]
+        );
+        assert_eq!(
+            report.to_string(),
+            "receivers[0] (imap user@example.com on imap.example.com:993, tls_mode implicit): \
+             connection ok, tls ok, auth ok, mailbox ok, 3 message(s), 1 unseen"
+        );
+    }
+
+    #[test]
+    fn test_record_failed_step() {
+        let mut report = imap_report();
+        let error = ConnectError::new(ConnectStage::Auth, "Login failed: bad password");
+        report.record(Err(error.into()), true, true);
+
+        assert!(!report.succeeded());
+        assert_eq!(
+            [report.connection, report.tls, report.auth, report.mailbox],
+            [
+                StepStatus::Ok,
+                StepStatus::Ok,
+                StepStatus::Failed,
+                StepStatus::Skipped
+            ]
+        );
+        assert!(
+            report
+                .to_string()
+                .ends_with("\n    Login failed: bad password")
+        );
+
+        // Without TLS the step is not used, and the sender has no mailbox
+        let mut report = imap_report();
+        report.record(Err(anyhow::anyhow!("Connection refused")), false, false);
+        assert_eq!(
+            [report.connection, report.tls, report.auth, report.mailbox],
+            [
+                StepStatus::Failed,
+                StepStatus::NotUsed,
+                StepStatus::Skipped,
+                StepStatus::NotUsed
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_json() {
+        let mut report = imap_report();
+        report.record(Ok(None), false, false);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["account"], "receivers[0]");
+        assert_eq!(json["tls"], "not_used");
+        assert_eq!(json["auth"], "ok");
+        assert_eq!(json["messages"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_every_account() {
+        // Bind and release a port so nothing listens on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut config: AppConfig = Config::builder()
+            .add_source(File::from_str(
+                include_str!("../config.toml"),
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        for receiver in &mut config.receivers {
+            receiver.host = "127.0.0.1".to_string();
+            receiver.port = port;
+            receiver.proxy = None;
+            receiver.tls_mode = Some(TlsMode::None);
+        }
+        config.sender.host = "127.0.0.1".to_string();
+        config.sender.port = port;
+        config.sender.proxy = None;
+
+        let reports = run(&config).await;
+
+        assert_eq!(reports.len(), config.receivers.len() + 1);
+        assert_eq!(reports.last().unwrap().account, "sender");
+        for report in &reports {
+            assert!(!report.succeeded());
+            assert_eq!(report.connection, StepStatus::Failed);
+            assert_eq!(report.auth, StepStatus::Skipped);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: credentials.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 23:05:48
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 23:05:48
+ */
+
+use crate::config::SecretCommand;
+use log::debug;
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+/// The output of a secret command and when it was obtained.
+struct CachedSecret {
+    secret: String,
+    obtained: Instant,
+}
+
+/// Outputs of secret commands, by command line.
+static CACHE: LazyLock<Mutex<HashMap<Vec<String>, CachedSecret>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the secret of `account`: the output of `command` if one is set, else
+/// `inline`. Commands run again once their cached output is older than the TTL;
+/// failures are not cached and name the account.
+pub fn resolve(
+    inline: &str,
+    command: Option<&SecretCommand>,
+    account: &str,
+) -> anyhow::Result<String> {
+    let Some(command) = command else {
+        return Ok(inline.to_string());
+    };
+
+    if let Some(cached) = CACHE.lock().unwrap().get(&command.command)
+        && cached.obtained.elapsed() < command.ttl()
+    {
+        return Ok(cached.secret.clone());
+    }
+
+    debug!("Running `{}` for the secret of {}", command, account);
+    let secret = run(command)
+        .map_err(|e| anyhow::anyhow!("Failed to get the secret of {}: {}", account, e))?;
+    let cached = CachedSecret {
+        secret: secret.clone(),
+        obtained: Instant::now(),
+    };
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(command.command.clone(), cached);
+    Ok(secret)
+}
+
+/// Like [`resolve`], running the command without blocking the async runtime.
+pub async fn resolve_async(
+    inline: &str,
+    command: Option<&SecretCommand>,
+    account: &str,
+) -> anyhow::Result<String> {
+    let Some(command) = command.cloned() else {
+        return Ok(inline.to_string());
+    };
+
+    let account = account.to_string();
+    tokio::task::spawn_blocking(move || resolve("", Some(&command), &account)).await?
+}
+
+/// Runs the command and returns its output without the trailing newline.
+fn run(command: &SecretCommand) -> anyhow::Result<String> {
+    let (program, args) = command
+        .command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("the command is empty"))?;
+
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| anyhow::anyhow!("`{}` could not be run: {}", command, e))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{}` failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let secret = String::from_utf8(output.stdout)
+        .map_err(|_| anyhow::anyhow!("`{}` printed invalid UTF-8", command))?
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+    if secret.is_empty() {
+        anyhow::bail!("`{}` printed nothing", command);
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(script: &str, ttl_seconds: Option<u64>) -> SecretCommand {
+        SecretCommand {
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            ttl_seconds,
+        }
+    }
+
+    #[test]
+    fn test_inline_secret() {
+        assert_eq!(resolve("p", None, "user on host").unwrap(), "p");
+    }
+
+    #[test]
+    fn test_command_output_is_cached_for_its_ttl() {
+        let counter =
+            std::env::temp_dir().join(format!("mail-forwarder-secret-{}", std::process::id()));
+        let script = format!(
+            "echo x >> {0}; echo secret-$(wc -l < {0})",
+            counter.display()
+        );
+
+        let cached = command(&script, None);
+        assert_eq!(resolve("", Some(&cached), "a").unwrap(), "secret-1");
+        assert_eq!(resolve("", Some(&cached), "a").unwrap(), "secret-1");
+
+        // The same command with a TTL of 0 is run every time
+        let uncached = command(&script, Some(0));
+        assert_eq!(resolve("", Some(&uncached), "a").unwrap(), "secret-2");
+        assert_eq!(resolve("", Some(&uncached), "a").unwrap(), "secret-3");
+
+        std::fs::remove_file(counter).unwrap();
+    }
+
+    #[test]
+    fn test_command_failure_names_the_account() {
+        let failing = command("echo 'permission denied' >&2; exit 2", None);
+        let error = resolve("", Some(&failing), "user@example.com on imap.example.com")
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with(
+            "Failed to get the secret of user@example.com on imap.example.com: `sh -c"
+        ));
+        assert!(error.ends_with("failed with exit status: 2: permission denied"));
+
+        let silent = command("true", None);
+        let error = resolve("", Some(&silent), "a").unwrap_err().to_string();
+        assert!(error.ends_with("printed nothing"));
+
+        let missing = SecretCommand {
+            command: vec!["/nonexistent/vault".to_string()],
+            ttl_seconds: None,
+        };
+        let error = resolve("", Some(&missing), "a").unwrap_err().to_string();
+        assert!(error.contains("`/nonexistent/vault` could not be run"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_async() {
+        let echo = command("echo async-secret", Some(0));
+        assert_eq!(
+            resolve_async("", Some(&echo), "a").await.unwrap(),
+            "async-secret"
+        );
+        assert_eq!(resolve_async("p", None, "a").await.unwrap(), "p");
+    }
+}
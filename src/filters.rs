@@ -0,0 +1,400 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: filters.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 17:05:12
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 17:05:12
+ */
+
+use crate::config::{FilterConfig, ReceiverConfig};
+use crate::rules::{any_address_matches, glob_match};
+use mail_parser::{Message, MessageParser};
+use regex::Regex;
+
+/// A filter from the configuration with its subject expression compiled.
+struct Filter {
+    config: FilterConfig,
+    subject: Option<Regex>,
+}
+
+impl Filter {
+    fn new(config: &FilterConfig) -> anyhow::Result<Self> {
+        let subject = config
+            .subject
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid subject filter: {}", e))?;
+
+        Ok(Self {
+            config: config.clone(),
+            subject,
+        })
+    }
+
+    /// Returns the descriptions of the matched conditions if every condition
+    /// that is set matches, `None` otherwise.
+    fn matches(&self, message: Option<&Message>, size: usize) -> Option<Vec<String>> {
+        let config = &self.config;
+        let mut matched = Vec::new();
+
+        if let Some(pattern) = &config.from {
+            if !message.is_some_and(|m| any_address_matches(m.from(), pattern)) {
+                return None;
+            }
+            matched.push(format!("from matches \"{}\"", pattern));
+        }
+
+        if let Some(pattern) = &config.to {
+            if !message.is_some_and(|m| {
+                any_address_matches(m.to(), pattern) || any_address_matches(m.cc(), pattern)
+            }) {
+                return None;
+            }
+            matched.push(format!("to matches \"{}\"", pattern));
+        }
+
+        if let Some(regex) = &self.subject {
+            if !message.is_some_and(|m| regex.is_match(m.subject().unwrap_or_default())) {
+                return None;
+            }
+            matched.push(format!("subject matches /{}/", regex));
+        }
+
+        if let Some(pattern) = &config.list_id {
+            let list_id = message.and_then(list_id)?;
+            if !glob_match(&pattern.to_lowercase(), &list_id.to_lowercase()) {
+                return None;
+            }
+            matched.push(format!("List-Id {}", list_id));
+        }
+
+        if let Some(expected) = config.auto_submitted {
+            if message.is_some_and(is_auto_submitted) != expected {
+                return None;
+            }
+            matched.push(
+                if expected {
+                    "auto-submitted"
+                } else {
+                    "not auto-submitted"
+                }
+                .to_string(),
+            );
+        }
+
+        if let Some(min_size) = config.min_size {
+            if size < min_size {
+                return None;
+            }
+            matched.push(format!("size {} >= {}", size, min_size));
+        }
+
+        if let Some(max_size) = config.max_size {
+            if size > max_size {
+                return None;
+            }
+            matched.push(format!("size {} <= {}", size, max_size));
+        }
+
+        if let Some(expected) = config.has_attachments {
+            if message.is_some_and(|m| m.attachment_count() > 0) != expected {
+                return None;
+            }
+            matched.push(
+                if expected {
+                    "has attachments"
+                } else {
+                    "has no attachments"
+                }
+                .to_string(),
+            );
+        }
+
+        Some(matched)
+    }
+}
+
+/// The include and exclude filters of a receiver.
+pub struct Filters {
+    include: Vec<Filter>,
+    exclude: Vec<Filter>,
+}
+
+impl Filters {
+    /// Compiles the receiver's filters, failing on invalid subject expressions.
+    pub fn new(config: &ReceiverConfig) -> anyhow::Result<Self> {
+        let compile = |filters: &[FilterConfig]| -> anyhow::Result<Vec<Filter>> {
+            filters.iter().map(Filter::new).collect()
+        };
+
+        Ok(Self {
+            include: compile(&config.include)?,
+            exclude: compile(&config.exclude)?,
+        })
+    }
+
+    /// Checks a raw RFC 5322 message against the filters.
+    /// Returns the reason it was filtered out, or `None` if it should be forwarded.
+    pub fn check(&self, raw: &[u8]) -> Option<String> {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return None;
+        }
+
+        let message = MessageParser::default().parse(raw);
+        let message = message.as_ref();
+
+        if !self.include.is_empty()
+            && !self
+                .include
+                .iter()
+                .any(|filter| filter.matches(message, raw.len()).is_some())
+        {
+            return Some("matches no include filter".to_string());
+        }
+
+        self.exclude.iter().enumerate().find_map(|(index, filter)| {
+            filter.matches(message, raw.len()).map(|matched| {
+                if matched.is_empty() {
+                    format!("exclude filter #{} matches everything", index + 1)
+                } else {
+                    format!("exclude filter #{}: {}", index + 1, matched.join(", "))
+                }
+            })
+        })
+    }
+}
+
+/// Extracts the list identifier from the List-Id header, e.g. `news.example.com`
+/// from `Newsletter <news.example.com>`.
+fn list_id(message: &Message) -> Option<String> {
+    let value = message.header_raw("List-Id")?.trim();
+    let id = match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    Some(id.trim().to_string())
+}
+
+/// Whether the message was generated automatically (RFC 3834), or sent in bulk.
+fn is_auto_submitted(message: &Message) -> bool {
+    let header = |name: &str| message.header_raw(name).map(keyword);
+
+    let auto_submitted = header("Auto-Submitted").is_some_and(|value| value != "no");
+    let bulk = header("Precedence")
+        .is_some_and(|value| matches!(value.as_str(), "bulk" | "junk" | "list"));
+
+    auto_submitted || bulk
+}
+
+/// The leading keyword of a header value, lowercased, without comments and
+/// parameters: `no` for `No (not automatic); foo=bar`.
+fn keyword(value: &str) -> String {
+    let mut uncommented = String::with_capacity(value.len());
+    let mut depth = 0usize;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if depth > 0 => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => uncommented.push(c),
+            _ => {}
+        }
+    }
+
+    uncommented
+        .split(|c: char| c == ';' || c.is_whitespace())
+        .find(|token| !token.is_empty())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEWSLETTER: &[u8] = b"From: News <news@lists.example.org>\r\n\
+To: me@example.com\r\n\
+Subject: Weekly digest\r\n\
+List-Id: Example News <news.lists.example.org>\r\n\
+Precedence: list\r\n\
+\r\n\
+This week in news.\r\n";
+
+    const AUTO_REPLY: &[u8] = b"From: colleague@example.com\r\n\
+To: me@example.com\r\n\
+Subject: Out of Office: Re: Lunch\r\n\
+Auto-Submitted: auto-replied\r\n\
+\r\n\
+I am away until Monday.\r\n";
+
+    const WITH_ATTACHMENT: &[u8] = b"From: boss@example.com\r\n\
+To: me@example.com\r\n\
+Subject: Report\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+See attached.\r\n\
+--b\r\n\
+Content-Type: application/pdf\r\n\
+Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQK\r\n\
+--b--\r\n";
+
+    fn receiver_filters(include: Vec<FilterConfig>, exclude: Vec<FilterConfig>) -> Filters {
+        Filters::new(&ReceiverConfig {
+            include,
+            exclude,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_no_filters_forward_everything() {
+        let filters = receiver_filters(vec![], vec![]);
+        assert_eq!(filters.check(NEWSLETTER), None);
+        assert_eq!(filters.check(AUTO_REPLY), None);
+    }
+
+    #[test]
+    fn test_exclude_mailing_lists_and_auto_replies() {
+        let filters = receiver_filters(
+            vec![],
+            vec![
+                FilterConfig {
+                    list_id: Some("*.example.org".to_string()),
+                    ..Default::default()
+                },
+                FilterConfig {
+                    auto_submitted: Some(true),
+                    ..Default::default()
+                },
+            ],
+        );
+
+        assert_eq!(
+            filters.check(NEWSLETTER).as_deref(),
+            Some("exclude filter #1: List-Id news.lists.example.org")
+        );
+        assert_eq!(
+            filters.check(AUTO_REPLY).as_deref(),
+            Some("exclude filter #2: auto-submitted")
+        );
+        assert_eq!(filters.check(WITH_ATTACHMENT), None);
+    }
+
+    #[test]
+    fn test_precedence_counts_as_auto_submitted() {
+        let filters = receiver_filters(
+            vec![],
+            vec![FilterConfig {
+                auto_submitted: Some(true),
+                ..Default::default()
+            }],
+        );
+        assert!(filters.check(NEWSLETTER).is_some());
+    }
+
+    #[test]
+    fn test_auto_submitted_no_with_comments_and_parameters() {
+        let filters = receiver_filters(
+            vec![],
+            vec![FilterConfig {
+                auto_submitted: Some(true),
+                ..Default::default()
+            }],
+        );
+
+        for value in [
+            "no (sent by a person)",
+            "  NO\r\n\t(folded)",
+            "(leading comment) no",
+            "no; reason=manual",
+        ] {
+            let message = format!(
+                "From: colleague@example.com\r\nAuto-Submitted: {}\r\n\r\nHi\r\n",
+                value
+            );
+            assert_eq!(filters.check(message.as_bytes()), None, "{}", value);
+        }
+
+        let message =
+            b"From: a@example.com\r\nAuto-Submitted: auto-generated (no reply)\r\n\r\nHi\r\n";
+        assert!(filters.check(message).is_some());
+    }
+
+    #[test]
+    fn test_include_requires_a_match() {
+        let filters = receiver_filters(
+            vec![FilterConfig {
+                from: Some("*@example.com".to_string()),
+                has_attachments: Some(true),
+                ..Default::default()
+            }],
+            vec![],
+        );
+
+        assert_eq!(filters.check(WITH_ATTACHMENT), None);
+        assert_eq!(
+            filters.check(AUTO_REPLY).as_deref(),
+            Some("matches no include filter")
+        );
+        assert_eq!(
+            filters.check(NEWSLETTER).as_deref(),
+            Some("matches no include filter")
+        );
+    }
+
+    #[test]
+    fn test_subject_regex_and_size() {
+        let filters = receiver_filters(
+            vec![],
+            vec![FilterConfig {
+                subject: Some("(?i)^out of office".to_string()),
+                max_size: Some(1024),
+                ..Default::default()
+            }],
+        );
+
+        let reason = filters.check(AUTO_REPLY).unwrap();
+        assert!(reason.contains("subject matches"), "{}", reason);
+        assert!(reason.contains(&format!("size {} <= 1024", AUTO_REPLY.len())));
+        assert_eq!(filters.check(NEWSLETTER), None);
+
+        let large_only = receiver_filters(
+            vec![],
+            vec![FilterConfig {
+                min_size: Some(AUTO_REPLY.len() + 1),
+                ..Default::default()
+            }],
+        );
+        assert_eq!(large_only.check(AUTO_REPLY), None);
+    }
+
+    #[test]
+    fn test_invalid_subject_regex() {
+        let config = ReceiverConfig {
+            exclude: vec![FilterConfig {
+                subject: Some("(unclosed".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(Filters::new(&config).is_err());
+    }
+}
@@ -12,87 +12,289 @@
  * Last Modified: 2026-02-27 16:30:43
  */
 
-use crate::config::ReceiverConfig;
-use crate::traits::{Email, MailReceiver};
+use crate::config::{ReceiverConfig, TlsMode};
+use crate::credentials;
+use crate::proxy;
+use crate::traits::{ConnectError, ConnectStage, Email, MailReceiver, MailboxStatus};
 use async_imap::Session;
+use async_imap::extensions::idle::IdleResponse;
+use async_imap::imap_proto::{MailboxDatum, Response};
+use async_imap::types::Uid;
 use async_native_tls::{TlsConnector, TlsStream};
 use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
 use futures::{StreamExt, pin_mut};
+use log::{debug, error, warn};
 use tokio::net::TcpStream;
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
 use std::collections::HashSet;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+type ImapSession = Session<ImapStream>;
+
+/// The connection to the IMAP server, either TLS protected or plaintext.
+#[derive(Debug)]
+enum ImapStream {
+    Tls(TlsStream<Compat<TcpStream>>),
+    Plain(Compat<TcpStream>),
+}
 
-type ImapSession = Session<TlsStream<Compat<TcpStream>>>;
+impl AsyncRead for ImapStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ImapStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::Tls(stream) => Pin::new(stream).poll_close(cx),
+            ImapStream::Plain(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
 
 pub struct ImapReceiver {
     config: ReceiverConfig,
+    server_ids: Option<HashSet<String>>,
+    uid_validity: Option<u32>,
+    /// The long-lived session kept open between operations in IDLE mode.
+    session: Option<ImapSession>,
+    /// Whether the server advertises IDLE, probed once per receiver.
+    supports_idle: Option<bool>,
+}
+
+/// Builds an email ID from a UID, qualified by the mailbox's UIDVALIDITY.
+/// A UID is only meaningful together with the UIDVALIDITY it was issued under.
+fn format_id(uid_validity: u32, uid: Uid) -> String {
+    format!("{}:{}", uid_validity, uid)
+}
+
+/// Splits an email ID into its UIDVALIDITY and UID, if it has that shape.
+fn parse_id(id: &str) -> Option<(u32, Uid)> {
+    let (uid_validity, uid) = id.split_once(':')?;
+    Some((uid_validity.parse().ok()?, uid.parse().ok()?))
+}
+
+/// Splits email IDs into the UIDs that belong to `uid_validity`, and the IDs that do not
+/// (issued under a previous UIDVALIDITY, or not UID based at all).
+fn partition_ids(ids: &[String], uid_validity: u32) -> (Vec<Uid>, Vec<String>) {
+    let mut uids = Vec::new();
+    let mut stale = Vec::new();
+
+    for id in ids {
+        match parse_id(id) {
+            Some((validity, uid)) if validity == uid_validity => uids.push(uid),
+            _ => stale.push(id.clone()),
+        }
+    }
+
+    (uids, stale)
 }
 
 impl ImapReceiver {
     pub fn new(config: ReceiverConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            server_ids: None,
+            uid_validity: None,
+            session: None,
+            supports_idle: None,
+        }
     }
 
-    async fn connect(&self) -> anyhow::Result<ImapSession> {
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let tcp_stream = TcpStream::connect(&addr)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", addr, e))?;
+    fn idle_enabled(&self) -> bool {
+        self.config.idle.unwrap_or(false) && self.supports_idle != Some(false)
+    }
 
-        let tls = TlsConnector::new();
-        let tls_stream = tls
-            .connect(&self.config.host, tcp_stream.compat())
+    /// Returns the kept-alive session in IDLE mode, or connects a new one.
+    async fn open_session(&mut self) -> anyhow::Result<ImapSession> {
+        match self.session.take() {
+            Some(session) => Ok(session),
+            None => self.connect().await,
+        }
+    }
+
+    /// Keeps the session for the next operation in IDLE mode, otherwise logs out.
+    async fn release_session(&mut self, mut session: ImapSession) -> anyhow::Result<()> {
+        if self.idle_enabled() {
+            self.session = Some(session);
+            return Ok(());
+        }
+
+        session
+            .logout()
             .await
-            .map_err(|e| anyhow::anyhow!("TLS connection failed: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Logout failed: {}", e))?;
+        Ok(())
+    }
 
-        let client = async_imap::Client::new(tls_stream);
+    async fn connect(&self) -> anyhow::Result<ImapSession> {
+        let tcp_stream = proxy::connect(
+            &self.config.host,
+            self.config.port,
+            self.config.proxy().as_ref(),
+        )
+        .await
+        .map_err(|e| ConnectError::new(ConnectStage::Connect, e.to_string()))?;
+
+        let stream = match self.config.tls_mode() {
+            TlsMode::Implicit => ImapStream::Tls(self.start_tls(tcp_stream.compat()).await?),
+            TlsMode::Starttls => {
+                // Upgrade the plaintext connection before any credentials are sent
+                let mut client = async_imap::Client::new(tcp_stream.compat());
+                client
+                    .run_command_and_check_ok("STARTTLS", None)
+                    .await
+                    .map_err(|e| {
+                        ConnectError::new(ConnectStage::Tls, format!("STARTTLS failed: {}", e))
+                    })?;
+                ImapStream::Tls(self.start_tls(client.into_inner()).await?)
+            }
+            TlsMode::None => ImapStream::Plain(tcp_stream.compat()),
+        };
+
+        let account = format!("{} on {}", self.config.username, self.config.host);
+        let password = credentials::resolve_async(
+            &self.config.password,
+            self.config.password_command.as_ref(),
+            &account,
+        )
+        .await
+        .map_err(|e| ConnectError::new(ConnectStage::Auth, e.to_string()))?;
+
+        let client = async_imap::Client::new(stream);
 
         let session = client
-            .login(&self.config.username, &self.config.password)
+            .login(&self.config.username, &password)
             .await
-            .map_err(|e| anyhow::anyhow!("Login failed: {:?}", e.0))?;
+            .map_err(|e| {
+                ConnectError::new(ConnectStage::Auth, format!("Login failed: {:?}", e.0))
+            })?;
 
         Ok(session)
     }
 
-    async fn fetch_emails_internal(
+    async fn start_tls(
         &self,
-        seen_ids: &HashSet<String>,
-    ) -> anyhow::Result<Vec<Email>> {
-        let mut session = self.connect().await?;
+        stream: Compat<TcpStream>,
+    ) -> anyhow::Result<TlsStream<Compat<TcpStream>>> {
+        TlsConnector::new()
+            .connect(&self.config.host, stream)
+            .await
+            .map_err(|e| {
+                ConnectError::new(ConnectStage::Tls, format!("TLS connection failed: {}", e)).into()
+            })
+    }
 
-        // Select the mailbox (default: INBOX)
+    /// Selects the configured mailbox and returns its UIDVALIDITY.
+    async fn select_mailbox(&self, session: &mut ImapSession) -> anyhow::Result<u32> {
         let mailbox = &self.config.imap_folder;
-        session
+        let selected = session
             .select(mailbox)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to select mailbox {}: {}", mailbox, e))?;
 
-        // Search for all unseen messages
+        selected
+            .uid_validity
+            .ok_or_else(|| anyhow::anyhow!("Mailbox {} did not report UIDVALIDITY", mailbox))
+    }
+
+    /// Logs a warning when the mailbox's UIDVALIDITY differs from the one the
+    /// tracked IDs were issued under. Old IDs then no longer identify any message,
+    /// so they are never used for deletion and get pruned from the seen IDs.
+    fn check_uid_validity(&mut self, uid_validity: u32, seen_ids: &HashSet<String>) {
+        let previous = self.uid_validity.or_else(|| {
+            seen_ids
+                .iter()
+                .filter_map(|id| parse_id(id))
+                .map(|(validity, _)| validity)
+                .find(|validity| *validity != uid_validity)
+        });
+
+        if let Some(previous) = previous
+            && previous != uid_validity
+        {
+            warn!(
+                "[{}] UIDVALIDITY of {} changed from {} to {}; previously tracked IDs are discarded",
+                self.config.username, self.config.imap_folder, previous, uid_validity
+            );
+        }
+
+        self.uid_validity = Some(uid_validity);
+    }
+
+    async fn fetch_emails_internal(
+        &mut self,
+        seen_ids: &HashSet<String>,
+    ) -> anyhow::Result<Vec<Email>> {
+        let mut session = self.open_session().await?;
+
+        // Select the mailbox (default: INBOX)
+        let uid_validity = self.select_mailbox(&mut session).await?;
+        self.check_uid_validity(uid_validity, seen_ids);
+
+        // Search for all unseen messages, by UID so other clients expunging can't shift them
         let search_result = session
-            .search("UNSEEN")
+            .uid_search("UNSEEN")
             .await
             .map_err(|e| anyhow::anyhow!("Search failed: {}", e))?;
 
         let mut emails = Vec::new();
 
-        let seq_nums: Vec<String> = search_result.iter().map(|n| n.to_string()).collect();
-        if seq_nums.is_empty() {
-            // Logout
-            session
-                .logout()
-                .await
-                .map_err(|e| anyhow::anyhow!("Logout failed: {}", e))?;
+        self.server_ids = Some(
+            search_result
+                .iter()
+                .map(|uid| format_id(uid_validity, *uid))
+                .collect(),
+        );
+
+        let uids: Vec<String> = search_result
+            .iter()
+            .filter(|uid| !seen_ids.contains(&format_id(uid_validity, **uid)))
+            .map(|uid| uid.to_string())
+            .collect();
+        if uids.is_empty() {
+            self.release_session(session).await?;
             return Ok(emails);
         }
 
-        let sequence_set = seq_nums.join(",");
+        let uid_set = uids.join(",");
 
-        // Fetch all unseen messages in one command
+        // Fetch all unseen messages in one command. PEEK leaves the \Seen flag alone,
+        // it is set explicitly once an email has been handled.
         {
             let mut fetch_stream = session
-                .fetch(sequence_set, "RFC822")
+                .uid_fetch(uid_set, "BODY.PEEK[]")
                 .await
                 .map_err(|e| anyhow::anyhow!("Fetch failed for messages: {}", e))?;
 
@@ -100,8 +302,8 @@ impl ImapReceiver {
                 let message = fetch_result
                     .map_err(|e| anyhow::anyhow!("Error reading fetch result: {}", e))?;
 
-                if let Some(body) = message.body() {
-                    let id = format!("{}", message.message);
+                if let (Some(uid), Some(body)) = (message.uid, message.body()) {
+                    let id = format_id(uid_validity, uid);
                     if !seen_ids.contains(&id) {
                         emails.push(Email {
                             id,
@@ -112,11 +314,7 @@ impl ImapReceiver {
             }
         }
 
-        // Logout
-        session
-            .logout()
-            .await
-            .map_err(|e| anyhow::anyhow!("Logout failed: {}", e))?;
+        self.release_session(session).await?;
 
         Ok(emails)
     }
@@ -128,46 +326,121 @@ impl MailReceiver for ImapReceiver {
         self.fetch_emails_internal(seen_ids).await
     }
 
-    async fn delete_email(&mut self, id: &str) -> anyhow::Result<()> {
-        let mut session = self.connect().await?;
+    fn server_ids(&self) -> Option<&HashSet<String>> {
+        self.server_ids.as_ref()
+    }
 
-        let mailbox = &self.config.imap_folder;
-        session
-            .select(mailbox)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to select mailbox {}: {}", mailbox, e))?;
+    async fn wait_for_new_mail(&mut self, timeout: Duration) -> anyhow::Result<bool> {
+        if !self.idle_enabled() {
+            return Ok(false);
+        }
 
-        // Mark the message as deleted
-        {
-            let store_stream = session
-                .store(id, "+FLAGS (\\Deleted)")
+        let mut session = self.open_session().await?;
+
+        if self.supports_idle.is_none() {
+            let capabilities = session
+                .capabilities()
                 .await
-                .map_err(|e| anyhow::anyhow!("Failed to mark message {} as deleted: {}", id, e))?;
-            pin_mut!(store_stream);
+                .map_err(|e| anyhow::anyhow!("Failed to query capabilities: {}", e))?;
+            let supported = capabilities.has_str("IDLE");
+            self.supports_idle = Some(supported);
+
+            if !supported {
+                warn!(
+                    "[{}] Server does not support IDLE, falling back to polling",
+                    self.config.username
+                );
+                self.release_session(session).await?;
+                return Ok(false);
+            }
+        }
 
-            // Consume the stream
-            while store_stream.next().await.is_some() {}
+        self.select_mailbox(&mut session).await?;
+
+        let mut handle = session.idle();
+        handle
+            .init()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to start IDLE: {}", e))?;
+
+        // Keep idling through unrelated updates (flag changes, expunges) until the
+        // server announces new messages or the refresh timeout elapses. The wait
+        // itself is bounded too, as keepalives from the server restart its timer.
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let (idle_wait, _interrupt) = handle.wait_with_timeout(remaining);
+            let Ok(response) = tokio::time::timeout(remaining, idle_wait).await else {
+                break;
+            };
+            let response = response.map_err(|e| anyhow::anyhow!("IDLE failed: {}", e))?;
+
+            match response {
+                IdleResponse::NewData(data) => {
+                    if let Response::MailboxData(MailboxDatum::Exists(count)) = data.parsed() {
+                        debug!(
+                            "[{}] IDLE reported {} messages in {}",
+                            self.config.username, count, self.config.imap_folder
+                        );
+                        break;
+                    }
+                }
+                IdleResponse::Timeout | IdleResponse::ManualInterrupt => break,
+            }
         }
 
-        // Expunge to permanently delete
-        {
-            let expunge_stream = session
-                .expunge()
+        let session = handle
+            .done()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to end IDLE: {}", e))?;
+        self.session = Some(session);
+
+        Ok(true)
+    }
+
+    async fn mark_seen(&mut self, ids: &[String]) -> anyhow::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut session = self.open_session().await?;
+        let uid_validity = self.select_mailbox(&mut session).await?;
+
+        let (uids, stale) = partition_ids(ids, uid_validity);
+        if !stale.is_empty() {
+            warn!(
+                "[{}] Not flagging {} messages whose IDs do not match UIDVALIDITY {}",
+                self.config.username,
+                stale.len(),
+                uid_validity
+            );
+        }
+
+        if !uids.is_empty() {
+            let uid_set = uids
+                .iter()
+                .map(|uid| uid.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let store_stream = session
+                .uid_store(&uid_set, "+FLAGS (\\Seen)")
                 .await
-                .map_err(|e| anyhow::anyhow!("Failed to expunge: {}", e))?;
-            pin_mut!(expunge_stream);
+                .map_err(|e| anyhow::anyhow!("Failed to mark messages as seen: {}", e))?;
+            pin_mut!(store_stream);
 
             // Consume the stream
-            while expunge_stream.next().await.is_some() {}
+            while store_stream.next().await.is_some() {}
         }
 
-        // Logout
-        session
-            .logout()
-            .await
-            .map_err(|e| anyhow::anyhow!("Logout failed: {}", e))?;
+        self.release_session(session).await
+    }
 
-        Ok(())
+    async fn delete_email(&mut self, id: &str) -> anyhow::Result<()> {
+        self.delete_emails(&[id.to_string()]).await
     }
 
     async fn delete_emails(&mut self, ids: &[String]) -> anyhow::Result<()> {
@@ -175,20 +448,41 @@ impl MailReceiver for ImapReceiver {
             return Ok(());
         }
 
-        let mut session = self.connect().await?;
+        let mut session = self.open_session().await?;
+
+        let uid_validity = self.select_mailbox(&mut session).await?;
+
+        // Never delete by IDs issued under another UIDVALIDITY: the same UID may now
+        // belong to a completely different message.
+        let (uids, stale) = partition_ids(ids, uid_validity);
+        if !stale.is_empty() {
+            error!(
+                "[{}] Refusing to delete {} messages whose IDs do not match UIDVALIDITY {}: {:?}",
+                self.config.username,
+                stale.len(),
+                uid_validity,
+                stale
+            );
+        }
 
-        let mailbox = &self.config.imap_folder;
-        session
-            .select(mailbox)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to select mailbox {}: {}", mailbox, e))?;
+        if uids.is_empty() {
+            self.release_session(session).await?;
+            return Err(anyhow::anyhow!(
+                "None of the {} messages could be deleted safely",
+                ids.len()
+            ));
+        }
 
-        let sequence_set = ids.join(",");
+        let uid_set = uids
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
 
         // Mark the messages as deleted
         {
             let store_stream = session
-                .store(sequence_set, "+FLAGS (\\Deleted)")
+                .uid_store(&uid_set, "+FLAGS (\\Deleted)")
                 .await
                 .map_err(|e| anyhow::anyhow!("Failed to mark messages as deleted: {}", e))?;
             pin_mut!(store_stream);
@@ -197,8 +491,24 @@ impl MailReceiver for ImapReceiver {
             while store_stream.next().await.is_some() {}
         }
 
-        // Expunge to permanently delete
-        {
+        // Expunge to permanently delete. With UIDPLUS only our own messages are
+        // expunged, leaving anything another client flagged as deleted alone.
+        let has_uidplus = session
+            .capabilities()
+            .await
+            .map(|caps| caps.has_str("UIDPLUS"))
+            .unwrap_or(false);
+
+        if has_uidplus {
+            let expunge_stream = session
+                .uid_expunge(&uid_set)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to expunge: {}", e))?;
+            pin_mut!(expunge_stream);
+
+            // Consume the stream
+            while expunge_stream.next().await.is_some() {}
+        } else {
             let expunge_stream = session
                 .expunge()
                 .await
@@ -209,14 +519,32 @@ impl MailReceiver for ImapReceiver {
             while expunge_stream.next().await.is_some() {}
         }
 
-        // Logout
-        session
-            .logout()
-            .await
-            .map_err(|e| anyhow::anyhow!("Logout failed: {}", e))?;
+        self.release_session(session).await?;
 
         Ok(())
     }
+
+    async fn check_connection(&mut self) -> anyhow::Result<MailboxStatus> {
+        let mut session = self.connect().await?;
+        let mailbox = &self.config.imap_folder;
+
+        // EXAMINE opens the mailbox read-only, so not even \Recent flags change
+        let examined = session.examine(mailbox).await.map_err(|e| {
+            ConnectError::new(
+                ConnectStage::Mailbox,
+                format!("Failed to examine mailbox {}: {}", mailbox, e),
+            )
+        })?;
+        let unseen = session.uid_search("UNSEEN").await.map_err(|e| {
+            ConnectError::new(ConnectStage::Mailbox, format!("Search failed: {}", e))
+        })?;
+        let _ = session.logout().await;
+
+        Ok(MailboxStatus {
+            messages: examined.exists as usize,
+            unseen: Some(unseen.len()),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -234,6 +562,7 @@ mod imap_receiver_tests {
             check_interval_seconds: Some(60),
             delete_after_forward: Some(false),
             imap_folder: "INBOX".to_string(),
+            ..Default::default()
         }
     }
 
@@ -259,4 +588,256 @@ mod imap_receiver_tests {
         // With test credentials, we expect this to fail
         // In real usage with proper credentials, it should succeed
     }
+
+    fn stand_in_config(port: u16, tls_mode: TlsMode) -> ReceiverConfig {
+        ReceiverConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: "test_user".to_string(),
+            password: "test_pass".to_string(),
+            protocol: "imap".to_string(),
+            tls_mode: Some(tls_mode),
+            imap_folder: "INBOX".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Starts a plaintext IMAP stand-in on localhost that accepts one connection,
+    /// answers every command with OK and records the commands it received.
+    /// A TLS handshake is recorded as `<TLS>` and ends the conversation. While
+    /// idling it sends a keepalive every 100ms until the client sends DONE.
+    async fn spawn_imap_stand_in() -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut transcript = Vec::new();
+
+            writer
+                .write_all(b"* OK [CAPABILITY IMAP4rev1 STARTTLS] stand-in ready\r\n")
+                .await
+                .unwrap();
+
+            loop {
+                let buf = reader.fill_buf().await.unwrap_or_default();
+                if buf.is_empty() {
+                    break;
+                }
+                // 0x16 is the TLS handshake record type of a ClientHello
+                if buf[0] == 0x16 {
+                    transcript.push("<TLS>".to_string());
+                    break;
+                }
+
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                let verb = command.split(' ').next().unwrap().to_string();
+                transcript.push(command.to_string());
+
+                let reply = match verb.as_str() {
+                    "LOGOUT" => format!("* BYE logging out\r\n{} OK LOGOUT completed\r\n", tag),
+                    "STARTTLS" => format!("{} OK Begin TLS negotiation now\r\n", tag),
+                    "EXAMINE" => {
+                        format!("* 3 EXISTS\r\n{} OK [READ-ONLY] EXAMINE completed\r\n", tag)
+                    }
+                    "UID" => format!("* SEARCH 4 5\r\n{} OK UID completed\r\n", tag),
+                    "SELECT" => format!(
+                        "* 3 EXISTS\r\n* OK [UIDVALIDITY 100] UIDs valid\r\n{} OK [READ-WRITE] SELECT completed\r\n",
+                        tag
+                    ),
+                    "CAPABILITY" => format!(
+                        "* CAPABILITY IMAP4rev1 IDLE\r\n{} OK CAPABILITY completed\r\n",
+                        tag
+                    ),
+                    "IDLE" => {
+                        writer.write_all(b"+ idling\r\n").await.unwrap();
+                        let mut done = String::new();
+                        let read_done = reader.read_line(&mut done);
+                        tokio::pin!(read_done);
+                        loop {
+                            tokio::select! {
+                                _ = &mut read_done => break,
+                                _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                                    writer.write_all(b"* OK Still here\r\n").await.unwrap();
+                                }
+                            }
+                        }
+                        transcript.push(done.trim_end().to_string());
+                        format!("{} OK IDLE terminated\r\n", tag)
+                    }
+                    _ => format!("{} OK {} completed\r\n", tag, verb),
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+
+                if verb == "LOGOUT" {
+                    break;
+                }
+            }
+
+            transcript
+        });
+
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn test_tls_mode_none_logs_in_over_plaintext() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let receiver = ImapReceiver::new(stand_in_config(port, TlsMode::None));
+
+        let mut session = receiver
+            .connect()
+            .await
+            .expect("plaintext login should succeed");
+        session.logout().await.unwrap();
+
+        let transcript = stand_in.await.unwrap();
+        assert_eq!(transcript.len(), 2);
+        assert!(transcript[0].starts_with("LOGIN "));
+        assert_eq!(transcript[1], "LOGOUT");
+    }
+
+    #[tokio::test]
+    async fn test_tls_mode_starttls_upgrades_before_login() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let receiver = ImapReceiver::new(stand_in_config(port, TlsMode::Starttls));
+
+        // The stand-in cannot complete a handshake, but it must be asked for one
+        // before any credentials are sent.
+        assert!(receiver.connect().await.is_err());
+
+        let transcript = stand_in.await.unwrap();
+        assert_eq!(
+            transcript,
+            vec!["STARTTLS".to_string(), "<TLS>".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tls_mode_implicit_starts_with_handshake() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let receiver = ImapReceiver::new(stand_in_config(port, TlsMode::Implicit));
+
+        assert!(receiver.connect().await.is_err());
+
+        let transcript = stand_in.await.unwrap();
+        assert_eq!(transcript, vec!["<TLS>".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_check_connection_counts_messages_read_only() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let mut receiver = ImapReceiver::new(stand_in_config(port, TlsMode::None));
+
+        let status = receiver.check_connection().await.unwrap();
+        assert_eq!(
+            status,
+            MailboxStatus {
+                messages: 3,
+                unseen: Some(2)
+            }
+        );
+
+        let transcript = stand_in.await.unwrap();
+        assert!(transcript[0].starts_with("LOGIN "));
+        assert_eq!(
+            transcript[1..],
+            ["EXAMINE \"INBOX\"", "UID SEARCH UNSEEN", "LOGOUT"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_connection_reports_failed_stage() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let mut receiver = ImapReceiver::new(stand_in_config(port, TlsMode::Implicit));
+        let error = receiver.check_connection().await.unwrap_err();
+        assert_eq!(ConnectError::stage_of(&error), ConnectStage::Tls);
+        stand_in.await.unwrap();
+
+        // Nothing listens on the stand-in's port any more
+        let error = receiver.check_connection().await.unwrap_err();
+        assert_eq!(ConnectError::stage_of(&error), ConnectStage::Connect);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_new_mail_without_idle() {
+        let mut receiver = ImapReceiver::new(get_test_config());
+
+        // Without `idle = true` the receiver must not connect and asks for polling
+        let result = receiver
+            .wait_for_new_mail(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(!result);
+        assert!(receiver.session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_new_mail_ends_despite_keepalives() {
+        let (port, stand_in) = spawn_imap_stand_in().await;
+        let mut receiver = ImapReceiver::new(ReceiverConfig {
+            idle: Some(true),
+            ..stand_in_config(port, TlsMode::None)
+        });
+
+        // The keepalives must not keep the wait going past its timeout
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            receiver.wait_for_new_mail(Duration::from_millis(500)),
+        )
+        .await
+        .expect("the wait should end after its timeout")
+        .unwrap();
+        assert!(result);
+        receiver.session.take().unwrap().logout().await.unwrap();
+
+        let transcript = stand_in.await.unwrap();
+        assert_eq!(
+            transcript[1..],
+            ["CAPABILITY", "SELECT \"INBOX\"", "IDLE", "DONE", "LOGOUT"]
+        );
+    }
+
+    #[test]
+    fn test_id_round_trip() {
+        let id = format_id(1700000000, 42);
+        assert_eq!(id, "1700000000:42");
+        assert_eq!(parse_id(&id), Some((1700000000, 42)));
+
+        // Sequence-number IDs from older versions are not UID based
+        assert_eq!(parse_id("42"), None);
+        assert_eq!(parse_id("abc:42"), None);
+    }
+
+    #[test]
+    fn test_partition_ids_rejects_other_uid_validity() {
+        let ids = vec![
+            "100:1".to_string(),
+            "100:7".to_string(),
+            "99:3".to_string(),
+            "5".to_string(),
+        ];
+
+        let (uids, stale) = partition_ids(&ids, 100);
+        assert_eq!(uids, vec![1, 7]);
+        assert_eq!(stale, vec!["99:3".to_string(), "5".to_string()]);
+    }
+
+    #[test]
+    fn test_check_uid_validity_tracks_current_value() {
+        let mut receiver = ImapReceiver::new(get_test_config());
+        let seen_ids = HashSet::from(["100:1".to_string()]);
+
+        receiver.check_uid_validity(100, &seen_ids);
+        assert_eq!(receiver.uid_validity, Some(100));
+
+        receiver.check_uid_validity(200, &seen_ids);
+        assert_eq!(receiver.uid_validity, Some(200));
+    }
 }
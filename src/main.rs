@@ -13,26 +13,50 @@
  */
 
 mod config;
+mod connection_test;
+mod credentials;
+mod dkim;
+mod filters;
 mod imap_receiver;
 mod notifications;
 mod pop3_receiver;
+mod proxy;
+mod rules;
 mod smtp_sender;
+mod spool;
+mod srs;
+mod state_store;
+mod tls;
 mod traits;
+mod validation;
 
-use clap::Parser;
-use config::{AppConfig, DEFAULT_CHECK_INTERVAL_SECONDS, ReceiverConfig};
+use clap::{Parser, Subcommand};
+use config::{
+    AppConfig, CONFIG_WATCH_INTERVAL_SECONDS, DEFAULT_CHECK_INTERVAL_SECONDS, DEFAULT_CONFIG_PATH,
+    DEFAULT_DRAIN_TIMEOUT_SECONDS, FilterAction, IDLE_TIMEOUT_SECONDS, ReceiverConfig, RuleConfig,
+    SPOOL_CHECK_INTERVAL_SECONDS,
+};
+use filters::Filters;
+use futures::future::join_all;
 use imap_receiver::ImapReceiver;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use pop3_receiver::Pop3Receiver;
+use rules::Route;
 use rustls::crypto;
 use smtp_sender::SmtpSender;
-use std::collections::HashSet;
+use spool::{RetryOutcome, Spool};
+use srs::Srs;
+use state_store::StateStore;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::signal;
-use tokio::sync::broadcast;
-use traits::{MailReceiver, MailSender, Notification};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use traits::{MailReceiver, MailSender, Notification, SendError};
 
 struct MultiWriter {
     writers: Vec<Box<dyn Write + Send + 'static>>,
@@ -57,8 +81,74 @@ impl Write for MultiWriter {
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, global = true)]
     config: Option<String>,
+
+    /// Poll every receiver a single time, deliver due spooled emails and exit;
+    /// the exit code is non-zero if anything failed
+    #[arg(long)]
+    once: bool,
+
+    /// Poll every receiver a single time and report what would be forwarded and
+    /// deleted, without sending, deleting, notifying or writing any state
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Reload the configuration whenever the config file changes, not only on SIGHUP
+    #[arg(long)]
+    watch_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the configuration and exit, listing every problem by its TOML key
+    CheckConfig,
+    /// Log in to every receiver and the sender without fetching or sending anything,
+    /// reporting per account whether the connection, TLS and login succeeded
+    TestConnection {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Decode an SRS address of the sender back into the address it replaced,
+    /// checking its hash and age, e.g. to look into a bounce
+    SrsReverse {
+        /// The SRS address, e.g. SRS0=HHHH=TT=example.org=alice@example.com
+        address: String,
+    },
+}
+
+/// Loads the configuration, exiting with every problem found if it cannot be
+/// deserialized or does not validate.
+fn load_config(path: Option<&str>) -> AppConfig {
+    let issues = match validation::load(path) {
+        Ok(config) => {
+            let issues = validation::validate(&config);
+            if issues.is_empty() {
+                return config;
+            }
+            issues
+        }
+        Err(issues) => issues,
+    };
+
+    eprintln!("Invalid config {}:", path.unwrap_or(DEFAULT_CONFIG_PATH));
+    for issue in &issues {
+        eprintln!("  {}", issue);
+    }
+    // Problems without a key are with the file itself, e.g. it was not found
+    if issues.iter().any(|issue| issue.key.is_empty()) {
+        if let Ok(path) = std::env::current_dir() {
+            eprintln!("Current search path: {:?}", path);
+        }
+        eprintln!(
+            "Please create a `config.toml` or set MAILFWD__... environment variables, or specify a config file with --config."
+        );
+    }
+    std::process::exit(1);
 }
 
 fn initialize_logger(config: &AppConfig) -> anyhow::Result<()> {
@@ -103,55 +193,195 @@ struct ProcessContext<'a> {
     username: &'a str,
     /// The SMTP sender instance used to forward emails.
     sender: &'a SmtpSender,
-    /// The target email address to forward to.
-    forward_to: &'a str,
+    /// The outbound spool; if set, emails are queued there instead of sent directly.
+    spool: Option<&'a Spool>,
+    /// The default target email addresses, used when no routing rule picks a destination.
+    forward_to: &'a [String],
+    /// The routing rules deciding where each email goes.
+    rules: &'a [RuleConfig],
+    /// The receiver's include/exclude filters.
+    filters: &'a Filters,
+    /// What to do at the source with emails that are not forwarded.
+    filter_action: FilterAction,
     /// The receiver instance, used to delete emails from the server if configured.
     receiver: &'a mut dyn MailReceiver,
-    /// A set of email IDs that have already been processed, to prevent duplicate processing.
-    seen_ids: &'a mut HashSet<String>,
+    /// The store of email IDs that have already been processed, to prevent duplicate processing.
+    seen_ids: &'a mut StateStore,
     /// Whether to delete emails from the source server after successful forwarding.
     delete_after_forward: bool,
     /// A list of notification handlers to trigger after successful processing.
     notifications: &'a [Box<dyn Notification>],
+    /// Only report what would be done, without sending, deleting or notifying.
+    dry_run: bool,
 }
 
 /// Processes a batch of fetched emails.
 ///
 /// This function handles the core logic of:
-/// 1. Forwarding each new email via SMTP.
-/// 2. Tracking successfully forwarded emails.
+/// 1. Filtering and routing each new email and forwarding it via SMTP,
+///    or queueing it in the spool if one is configured.
+/// 2. Tracking successfully forwarded and filtered emails.
 /// 3. Deleting successfully forwarded emails from the source server (if configured).
 /// 4. Triggering notifications for successfully processed emails.
-async fn process_emails(ctx: &mut ProcessContext<'_>, emails: Vec<traits::Email>) {
+///
+/// Returns the number of emails that failed to be forwarded or deleted.
+async fn process_emails(ctx: &mut ProcessContext<'_>, emails: Vec<traits::Email>) -> usize {
+    let mut failures = 0;
     let mut to_delete = Vec::new();
+    let mut to_mark_seen = Vec::new();
+    let mut filtered_to_delete = Vec::new();
     let mut successfully_processed = Vec::new();
 
     // Step 1 & 2: Forward emails and track successes
     for email in emails {
-        // Skip if we've already seen this email ID in the current session
+        // Skip if we've already forwarded this email ID
         if ctx.seen_ids.contains(&email.id) {
             continue;
         }
 
         info!("[{}] Processing new email ID: {}", ctx.username, email.id);
 
-        match ctx.sender.send_email(&email, ctx.forward_to).await {
+        let destinations = match ctx.filters.check(&email.content) {
+            Some(reason) => Err(reason),
+            None => match rules::route(ctx.rules, &email.content, ctx.forward_to) {
+                // Handled like a filtered email, or it would be retried forever
+                Route::Forward(destinations) if destinations.is_empty() => {
+                    Err("no routing rule matched and no default destination".to_string())
+                }
+                Route::Forward(destinations) => Ok(destinations),
+                Route::Skip => Err("skipped by routing rules".to_string()),
+            },
+        };
+
+        let destinations = match destinations {
+            Ok(destinations) => destinations,
+            Err(reason) if ctx.dry_run => {
+                info!(
+                    "[{}] [dry-run] Would not forward email {}: {} (action: {:?})",
+                    ctx.username, email.id, reason, ctx.filter_action
+                );
+                continue;
+            }
+            Err(reason) => {
+                info!(
+                    "[{}] Not forwarding email {}: {} (action: {:?})",
+                    ctx.username, email.id, reason, ctx.filter_action
+                );
+                // Remember the decision so the email is not evaluated again
+                if let Err(e) = ctx.seen_ids.insert(&email.id) {
+                    error!(
+                        "[{}] Failed to persist seen state for email {}: {:?}",
+                        ctx.username, email.id, e
+                    );
+                }
+                match ctx.filter_action {
+                    FilterAction::Leave => {}
+                    FilterAction::MarkSeen => to_mark_seen.push(email.id),
+                    FilterAction::Delete => filtered_to_delete.push(email.id),
+                }
+                continue;
+            }
+        };
+
+        if ctx.dry_run {
+            if !report_dry_run(ctx, &email, &destinations) {
+                failures += 1;
+            }
+            continue;
+        }
+
+        // With a spool, the email is handed over once it is durably queued
+        let result = match ctx.spool {
+            Some(spool) => spool
+                .enqueue(ctx.username, &email, &destinations)
+                .map(|_| "queued")
+                .map_err(|e| SendError::transient(format!("{:#}", e))),
+            None => ctx
+                .sender
+                .send_email(&email, &destinations)
+                .await
+                .map(|_| "forwarded"),
+        };
+
+        match result {
+            Ok(outcome) => {
+                info!(
+                    "[{}] Successfully {} email {} to {}",
+                    ctx.username,
+                    outcome,
+                    email.id,
+                    destinations.join(", ")
+                );
+                // Mark as seen to avoid reprocessing, also across restarts
+                if let Err(e) = ctx.seen_ids.insert(&email.id) {
+                    error!(
+                        "[{}] Failed to persist seen state for email {}: {:?}",
+                        ctx.username, email.id, e
+                    );
+                }
+                // Queue for deletion if forwarding succeeded, otherwise flag it as seen
+                if ctx.delete_after_forward {
+                    to_delete.push(email.id.clone());
+                } else {
+                    to_mark_seen.push(email.id.clone());
+                }
+                // Queue for notification, spooled emails are notified once delivered
+                if ctx.spool.is_none() {
+                    successfully_processed.push((email, destinations));
+                }
+            }
+            Err(e) if e.transient => {
+                failures += 1;
+                warn!(
+                    "[{}] Failed to forward email {}, will retry on the next check: {}",
+                    ctx.username, email.id, e
+                );
+            }
+            Err(e) => {
+                failures += 1;
+                // Retrying would only fail the same way: quarantine the email by
+                // leaving it on the server untouched, but never trying it again
+                error!(
+                    "[{}] Permanent failure forwarding email {} (code: {:?}, status: {:?}), not retrying: {}",
+                    ctx.username, email.id, e.code, e.enhanced_status, e
+                );
+                if let Err(e) = ctx.seen_ids.insert(&email.id) {
+                    error!(
+                        "[{}] Failed to persist seen state for email {}: {:?}",
+                        ctx.username, email.id, e
+                    );
+                }
+                notify_failure(ctx.notifications, ctx.username, &email, &destinations, &e).await;
+            }
+        }
+    }
+
+    if !to_mark_seen.is_empty()
+        && let Err(e) = ctx.receiver.mark_seen(&to_mark_seen).await
+    {
+        error!("[{}] Failed to mark emails as seen: {:?}", ctx.username, e);
+    }
+
+    if !filtered_to_delete.is_empty() {
+        match ctx.receiver.delete_emails(&filtered_to_delete).await {
             Ok(_) => {
                 info!(
-                    "[{}] Successfully forwarded email {}",
-                    ctx.username, email.id
+                    "[{}] Deleted {} filtered emails from server",
+                    ctx.username,
+                    filtered_to_delete.len()
                 );
-                // Mark as seen to avoid reprocessing
-                ctx.seen_ids.insert(email.id.clone());
-                // Queue for deletion if forwarding succeeded
-                to_delete.push(email.id.clone());
-                // Queue for notification
-                successfully_processed.push(email);
+                if let Err(e) = ctx.seen_ids.remove_all(&filtered_to_delete) {
+                    error!(
+                        "[{}] Failed to persist seen state after deletion: {:?}",
+                        ctx.username, e
+                    );
+                }
             }
             Err(e) => {
+                failures += filtered_to_delete.len();
                 error!(
-                    "[{}] Failed to forward email {}: {:?}",
-                    ctx.username, email.id, e
+                    "[{}] Failed to delete filtered emails: {:?}",
+                    ctx.username, e
                 );
             }
         }
@@ -167,13 +397,17 @@ async fn process_emails(ctx: &mut ProcessContext<'_>, emails: Vec<traits::Email>
                     to_delete.len()
                 );
                 // Remove deleted emails from seen_ids to prevent memory leaks over time
-                for id in &to_delete {
-                    ctx.seen_ids.remove(id);
+                if let Err(e) = ctx.seen_ids.remove_all(&to_delete) {
+                    error!(
+                        "[{}] Failed to persist seen state after deletion: {:?}",
+                        ctx.username, e
+                    );
                 }
                 // Only notify if deletion also succeeded
                 successfully_processed
             }
             Err(e) => {
+                failures += to_delete.len();
                 error!("[{}] Failed to delete emails: {:?}", ctx.username, e);
                 // Skip notifications if deletion fails, to avoid false positives
                 // or duplicate notifications if the email is fetched again later.
@@ -186,9 +420,9 @@ async fn process_emails(ctx: &mut ProcessContext<'_>, emails: Vec<traits::Email>
     };
 
     // Step 4: Trigger notifications
-    for email in notify_emails {
+    for (email, destinations) in notify_emails {
         for notification in ctx.notifications {
-            if let Err(e) = notification.notify(&email, ctx.forward_to).await {
+            if let Err(e) = notification.notify(&email, &destinations).await {
                 error!(
                     "[{}] Failed to send notification for email {}: {:?}",
                     ctx.username, email.id, e
@@ -196,19 +430,309 @@ async fn process_emails(ctx: &mut ProcessContext<'_>, emails: Vec<traits::Email>
             }
         }
     }
+
+    failures
+}
+
+/// Reports what forwarding an email would do: its destination, the headers
+/// added to it and whether it would be deleted from the server.
+/// Returns false if forwarding it would fail.
+fn report_dry_run(
+    ctx: &ProcessContext<'_>,
+    email: &traits::Email,
+    destinations: &[String],
+) -> bool {
+    match ctx.sender.preview(email, destinations) {
+        Ok(preview) => {
+            info!(
+                "[{}] [dry-run] Would forward email {} to {} (envelope from <{}>), {}: {}; then {}",
+                ctx.username,
+                email.id,
+                destinations.join(", "),
+                preview.envelope_from.as_deref().unwrap_or_default(),
+                if preview.attached {
+                    "attached to a new email with headers"
+                } else {
+                    "adding headers"
+                },
+                if preview.added_headers.is_empty() {
+                    "none".to_string()
+                } else {
+                    preview.added_headers.join(", ")
+                },
+                if ctx.delete_after_forward {
+                    "delete it from the server"
+                } else {
+                    "mark it as seen"
+                }
+            );
+            true
+        }
+        Err(e) => {
+            error!(
+                "[{}] [dry-run] Forwarding email {} to {} would fail: {}",
+                ctx.username,
+                email.id,
+                destinations.join(", "),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Notifies all handlers about an email that was given up on.
+async fn notify_failure(
+    notifications: &[Box<dyn Notification>],
+    username: &str,
+    email: &traits::Email,
+    target_addresses: &[String],
+    error: &SendError,
+) {
+    for notification in notifications {
+        if let Err(e) = notification
+            .notify_failure(email, target_addresses, error)
+            .await
+        {
+            error!(
+                "[{}] Failed to send failure notification for email {}: {:?}",
+                username, email.id, e
+            );
+        }
+    }
+}
+
+/// The reloadable dependencies of every task. Tasks read them afresh for each
+/// batch, so a reload replaces all of them at once.
+#[derive(Clone)]
+struct Outbound {
+    sender: Arc<SmtpSender>,
+    rules: Arc<Vec<RuleConfig>>,
+    notifications: Arc<Vec<Box<dyn Notification>>>,
+}
+
+/// Dependencies shared by all receiver tasks.
+#[derive(Clone)]
+struct SharedContext {
+    outbound: watch::Receiver<Outbound>,
+    spool: Option<Arc<Spool>>,
+    state_dir: Option<String>,
+    dry_run: bool,
+}
+
+/// Attempts delivery of every spooled email that is due, scheduling retries
+/// for failures and notifying for successful deliveries.
+/// Returns the number of emails that could not be delivered.
+async fn deliver_spooled(
+    spool: &Spool,
+    sender: &SmtpSender,
+    notifications: &[Box<dyn Notification>],
+) -> usize {
+    let due = match spool.due() {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Failed to read the spool: {:?}", e);
+            return 1;
+        }
+    };
+
+    let mut failures = 0;
+    for mut spooled in due {
+        let targets = spooled.entry.target_addresses.clone();
+
+        match sender.send_email(&spooled.email, &targets).await {
+            Ok(_) => {
+                info!(
+                    "[{}] Successfully forwarded spooled email {} to {}",
+                    spooled.entry.receiver,
+                    spooled.email.id,
+                    targets.join(", ")
+                );
+                if let Err(e) = spool.complete(&spooled) {
+                    error!(
+                        "[{}] Failed to remove email {} from the spool: {:?}",
+                        spooled.entry.receiver, spooled.email.id, e
+                    );
+                }
+                for notification in notifications {
+                    if let Err(e) = notification.notify(&spooled.email, &targets).await {
+                        error!(
+                            "[{}] Failed to send notification for email {}: {:?}",
+                            spooled.entry.receiver, spooled.email.id, e
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                match spool.fail(&mut spooled, &e) {
+                    Ok(RetryOutcome::Retry(delay)) => warn!(
+                        "[{}] Failed to forward spooled email {} (attempt {}), retrying in {}s: {}",
+                        spooled.entry.receiver,
+                        spooled.email.id,
+                        spooled.entry.attempts,
+                        delay.as_secs(),
+                        e
+                    ),
+                    Ok(RetryOutcome::DeadLettered) => {
+                        error!(
+                            "[{}] Giving up on email {} after {} attempts ({} failure), moved to {}: {}",
+                            spooled.entry.receiver,
+                            spooled.email.id,
+                            spooled.entry.attempts,
+                            if e.transient {
+                                "transient"
+                            } else {
+                                "permanent"
+                            },
+                            spool.dead_dir().display(),
+                            e
+                        );
+                        notify_failure(
+                            notifications,
+                            &spooled.entry.receiver,
+                            &spooled.email,
+                            &targets,
+                            &e,
+                        )
+                        .await;
+                    }
+                    Err(spool_err) => error!(
+                        "[{}] Failed to record delivery failure of email {}: {:?}",
+                        spooled.entry.receiver, spooled.email.id, spool_err
+                    ),
+                }
+            }
+        }
+    }
+
+    failures
+}
+
+/// Runs the delivery loop of the outbound spool, woken up by newly queued
+/// emails and periodically for retries.
+async fn run_spool_task(
+    spool: Arc<Spool>,
+    outbound: watch::Receiver<Outbound>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(SPOOL_CHECK_INTERVAL_SECONDS));
+
+    loop {
+        // A due tick must not start another delivery once shutdown was requested
+        tokio::select! {
+            biased;
+            _ = shutdown_rx.recv() => {
+                info!("[spool] Received shutdown signal. Stopping task...");
+                break;
+            }
+            _ = ticker.tick() => {}
+            _ = spool.notified() => {}
+        }
+
+        let current = outbound.borrow().clone();
+        deliver_spooled(&spool, &current.sender, &current.notifications).await;
+    }
+}
+
+/// Loads the seen IDs of a receiver, logging why if that fails.
+///
+/// Note: Without a `state_dir`, restarting the program with delete_after_forward = false
+/// will cause all existing POP3 emails to be forwarded again because seen_ids is not persisted.
+/// For IMAP, it only fetches UNSEEN emails, so it's less of an issue.
+/// seen_ids is reconciled against the server after every fetch, so it only grows
+/// with the mailbox itself; the optional max count/age limits bound it further.
+/// A dry run reads the state to skip known emails, but never writes it.
+fn load_state(
+    receiver_config: &ReceiverConfig,
+    state_dir: Option<&str>,
+    dry_run: bool,
+) -> Option<StateStore> {
+    let username = &receiver_config.username;
+    match state_dir {
+        Some(dir) if dry_run => match StateStore::open_read_only(dir, receiver_config) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                error!("[{}] Failed to load receiver state: {:?}", username, e);
+                None
+            }
+        },
+        Some(dir) => match StateStore::open(dir, receiver_config) {
+            Ok(store) => {
+                info!(
+                    "[{}] Loaded {} seen email IDs from {}",
+                    username,
+                    store.len(),
+                    store
+                        .path()
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default()
+                );
+                Some(store)
+            }
+            Err(e) => {
+                // Refuse to start rather than re-forwarding the whole mailbox
+                error!("[{}] Failed to load receiver state: {:?}", username, e);
+                None
+            }
+        },
+        None => Some(StateStore::in_memory(receiver_config)),
+    }
 }
 
 /// Runs the main loop for a single email receiver account.
 ///
 /// This task periodically polls the source server for new emails,
-/// processes them, and handles graceful shutdown.
+/// processes them, and handles graceful shutdown. With `once`, it polls a
+/// single time instead and returns whether that cycle fully succeeded.
 async fn run_receiver_task(
     receiver_config: ReceiverConfig,
-    sender: Arc<SmtpSender>,
-    forward_to: String,
-    notifications: Arc<Vec<Box<dyn Notification>>>,
+    forward_to: Vec<String>,
+    state: ReceiverState,
+    shared: SharedContext,
+    once: bool,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> bool {
+    let receiver: Box<dyn MailReceiver> = match receiver_config.protocol.as_str() {
+        "imap" => Box::new(ImapReceiver::new(receiver_config.clone())),
+        "pop3" => Box::new(Pop3Receiver::new(receiver_config.clone())),
+        other => {
+            error!(
+                "[{}] Unknown protocol {:?}",
+                receiver_config.username, other
+            );
+            return false;
+        }
+    };
+
+    run_receiver(
+        receiver,
+        receiver_config,
+        forward_to,
+        state,
+        shared,
+        once,
+        shutdown_rx,
+    )
+    .await
+}
+
+/// The loop of [`run_receiver_task`], with the receiver for its protocol.
+async fn run_receiver(
+    mut receiver: Box<dyn MailReceiver>,
+    receiver_config: ReceiverConfig,
+    forward_to: Vec<String>,
+    state: ReceiverState,
+    shared: SharedContext,
+    once: bool,
     mut shutdown_rx: broadcast::Receiver<()>,
-) {
+) -> bool {
+    let SharedContext {
+        outbound,
+        spool,
+        state_dir,
+        dry_run,
+    } = shared;
     let host = receiver_config.host.clone();
     let username = receiver_config.username.clone();
     let interval_seconds = receiver_config
@@ -217,54 +741,505 @@ async fn run_receiver_task(
         .max(10);
 
     info!(
-        "Starting task for {}:{} ({}) - Protocol: {} - Interval: {}s",
-        host, receiver_config.port, username, receiver_config.protocol, interval_seconds
+        "Starting task for {}:{} ({}) - Protocol: {} - Interval: {}s{} - Forwarding to: {}",
+        host,
+        receiver_config.port,
+        username,
+        receiver_config.protocol,
+        interval_seconds,
+        if receiver_config.idle.unwrap_or(false) {
+            " - IDLE"
+        } else {
+            ""
+        },
+        forward_to.join(", ")
     );
 
-    #[allow(clippy::wildcard_in_or_patterns)]
-    let mut receiver: Box<dyn MailReceiver> = match receiver_config.protocol.as_str() {
-        "imap" => Box::new(ImapReceiver::new(receiver_config.clone())),
-        "pop3" | _ => Box::new(Pop3Receiver::new(receiver_config.clone())),
+    // A restarted receiver takes over the seen IDs of its previous task, once
+    // that has finished its batch and released them
+    let mut state = state.lock_owned().await;
+    if shutdown_rx.try_recv().is_ok() {
+        info!("[{}] Received shutdown signal. Stopping task...", username);
+        return true;
+    }
+    let store = match state.take() {
+        Some(store) => {
+            info!(
+                "[{}] Taking over {} seen email IDs from the previous task",
+                username,
+                store.len()
+            );
+            store
+        }
+        None => match load_state(&receiver_config, state_dir.as_deref(), dry_run) {
+            Some(store) => store,
+            None => return false,
+        },
     };
+    let seen_ids = state.insert(store);
 
-    // Note: For POP3, if delete_after_forward is false, restarting the program
-    // will cause all existing emails to be forwarded again because seen_ids is not persisted.
-    // For IMAP, it only fetches UNSEEN emails, so it's less of an issue.
-    // Also, if delete_after_forward is false, seen_ids will grow indefinitely,
-    // which could be a memory leak for long-running processes with many emails.
-    let mut seen_ids: HashSet<String> = HashSet::new();
+    let filters = match Filters::new(&receiver_config) {
+        Ok(filters) => filters,
+        Err(e) => {
+            error!("[{}] Invalid filter configuration: {:?}", username, e);
+            return false;
+        }
+    };
+    let filter_action = receiver_config.filter_action.unwrap_or_default();
     let delete_after_forward = receiver_config.delete_after_forward.unwrap_or(false);
+    let seen_ids_max_count = receiver_config.seen_ids_max_count;
+    let seen_ids_max_age = receiver_config
+        .seen_ids_max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
 
     let mut ticker = tokio::time::interval(Duration::from_secs(interval_seconds));
     ticker.tick().await;
 
+    // In IDLE mode, fetch right away and then wait for the server to push new mail.
+    // The interval is only used to back off after errors or if IDLE is unsupported.
+    let mut use_idle = receiver_config.idle.unwrap_or(false) && !once;
+    let mut skip_wait = use_idle || once;
+
     loop {
-        tokio::select! {
-            _ = shutdown_rx.recv() => {
-                info!("[{}] Received shutdown signal. Stopping task...", username);
-                break;
+        if skip_wait {
+            skip_wait = false;
+        } else if use_idle {
+            tokio::select! {
+                biased;
+                _ = shutdown_rx.recv() => {
+                    info!("[{}] Received shutdown signal. Stopping task...", username);
+                    break;
+                }
+                result = receiver.wait_for_new_mail(Duration::from_secs(IDLE_TIMEOUT_SECONDS)) => {
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            use_idle = false;
+                            ticker.reset();
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("[{}] IDLE failed, retrying after interval: {:?}", username, e);
+                            tokio::select! {
+                                biased;
+                                _ = shutdown_rx.recv() => {
+                                    info!("[{}] Received shutdown signal. Stopping task...", username);
+                                    break;
+                                }
+                                _ = ticker.tick() => {}
+                            }
+                        }
+                    }
+                }
+            }
+        } else {
+            // The shutdown signal wins over a tick that came due during the last
+            // batch, so no new batch starts while the tasks are being drained
+            tokio::select! {
+                biased;
+                _ = shutdown_rx.recv() => {
+                    info!("[{}] Received shutdown signal. Stopping task...", username);
+                    break;
+                }
+                _ = ticker.tick() => {}
             }
-            _ = ticker.tick() => {}
         }
 
-        match receiver.fetch_emails(&seen_ids).await {
+        let succeeded = match receiver.fetch_emails(&seen_ids.ids()).await {
             Ok(emails) => {
+                // Forget IDs the server no longer reports. Receivers that cannot
+                // list them are bounded by the optional limits instead.
+                match receiver.server_ids() {
+                    Some(server_ids) => match seen_ids.retain_present(server_ids) {
+                        Ok(0) => {}
+                        Ok(pruned) => debug!(
+                            "[{}] Pruned {} seen IDs no longer on the server",
+                            username, pruned
+                        ),
+                        Err(e) => error!("[{}] Failed to prune seen IDs: {:?}", username, e),
+                    },
+                    None => match seen_ids.enforce_limits(seen_ids_max_count, seen_ids_max_age) {
+                        Ok(0) => {}
+                        Ok(evicted) => debug!(
+                            "[{}] Evicted {} seen IDs over the configured limits",
+                            username, evicted
+                        ),
+                        Err(e) => error!("[{}] Failed to evict seen IDs: {:?}", username, e),
+                    },
+                }
+
+                // Read for every batch, so a reload applies from the next one on
+                let Outbound {
+                    sender,
+                    rules,
+                    notifications,
+                } = outbound.borrow().clone();
                 let mut ctx = ProcessContext {
                     username: &username,
                     sender: &sender,
+                    spool: spool.as_deref(),
                     forward_to: &forward_to,
+                    rules: &rules,
+                    filters: &filters,
+                    filter_action,
                     receiver: receiver.as_mut(),
-                    seen_ids: &mut seen_ids,
+                    seen_ids,
                     delete_after_forward,
                     notifications: &notifications,
+                    dry_run,
                 };
-                process_emails(&mut ctx, emails).await;
+                process_emails(&mut ctx, emails).await == 0
             }
             Err(e) => {
                 error!("[{}] Error fetching emails: {:?}", username, e);
+                false
             }
+        };
+
+        if once {
+            return succeeded;
         }
     }
+
+    true
+}
+
+/// A spawned task and the channel telling it to stop.
+struct Task {
+    stop_tx: broadcast::Sender<()>,
+    handle: JoinHandle<bool>,
+}
+
+/// The seen IDs of a receiver, held by its task while it runs and handed to
+/// the next one when a reload restarts it. Empty until the first task loads them.
+type ReceiverState = Arc<tokio::sync::Mutex<Option<StateStore>>>;
+
+/// The settings a receiver task was started with.
+#[derive(Debug, Clone, PartialEq)]
+struct ReceiverSettings {
+    config: ReceiverConfig,
+    forward_to: Vec<String>,
+}
+
+impl ReceiverSettings {
+    fn new(config: &ReceiverConfig, default_forward_to: &[String]) -> Self {
+        Self {
+            config: config.clone(),
+            forward_to: config.forward_to(default_forward_to),
+        }
+    }
+
+    fn key(&self) -> String {
+        StateStore::key(&self.config)
+    }
+}
+
+/// A running receiver task, with the settings it was started with.
+struct RunningReceiver {
+    settings: ReceiverSettings,
+    state: ReceiverState,
+    task: Task,
+}
+
+fn spawn_receiver(
+    settings: ReceiverSettings,
+    state: ReceiverState,
+    shared: &SharedContext,
+    once: bool,
+) -> RunningReceiver {
+    let (stop_tx, stop_rx) = broadcast::channel(1);
+    let task = run_receiver_task(
+        settings.config.clone(),
+        settings.forward_to.clone(),
+        state.clone(),
+        shared.clone(),
+        once,
+        stop_rx,
+    );
+
+    RunningReceiver {
+        settings,
+        state,
+        task: Task {
+            stop_tx,
+            handle: tokio::spawn(task),
+        },
+    }
+}
+
+/// What a reload does with each receiver, by key.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ReloadPlan {
+    /// Running with the same settings, left alone.
+    unchanged: Vec<String>,
+    /// Running with other settings, or no longer running: started again with
+    /// the new settings, keeping their seen IDs.
+    restarted: Vec<String>,
+    /// Running, but no longer configured: stopped.
+    removed: Vec<String>,
+    /// Configured, but not running yet: started.
+    added: Vec<String>,
+}
+
+/// Compares the running receivers, with whether their task has ended, to the
+/// receivers of a new configuration.
+fn plan_reload<'a>(
+    running: impl IntoIterator<Item = (&'a ReceiverSettings, bool)>,
+    config: &AppConfig,
+) -> ReloadPlan {
+    let wanted: HashMap<String, ReceiverSettings> = config
+        .receivers
+        .iter()
+        .map(|receiver_config| {
+            let settings = ReceiverSettings::new(receiver_config, &config.forward_to);
+            (settings.key(), settings)
+        })
+        .collect();
+
+    let mut plan = ReloadPlan::default();
+    let mut running_keys = HashSet::new();
+    for (settings, finished) in running {
+        let key = settings.key();
+        match wanted.get(&key) {
+            None => plan.removed.push(key.clone()),
+            // A task that ended, e.g. because its state failed to load, gets another try
+            Some(new) if *new == *settings && !finished => plan.unchanged.push(key.clone()),
+            Some(_) => plan.restarted.push(key.clone()),
+        }
+        running_keys.insert(key);
+    }
+    plan.added = config
+        .receivers
+        .iter()
+        .map(StateStore::key)
+        .filter(|key| !running_keys.contains(key))
+        .collect();
+
+    plan
+}
+
+/// Tells the tasks to stop and waits for them. Tasks only see the signal between
+/// batches, so one halfway through forwarding finishes its batch first, unless
+/// that takes longer than `drain_timeout`. Returns how many tasks were aborted.
+async fn stop_tasks(mut tasks: Vec<Task>, drain_timeout: Duration) -> usize {
+    for task in &tasks {
+        let _ = task.stop_tx.send(());
+    }
+
+    let handles = tasks.iter_mut().map(|task| &mut task.handle);
+    if tokio::time::timeout(drain_timeout, join_all(handles))
+        .await
+        .is_ok()
+    {
+        return 0;
+    }
+
+    let unfinished: Vec<_> = tasks
+        .iter()
+        .filter(|task| !task.handle.is_finished())
+        .collect();
+    warn!(
+        "{} task(s) did not finish within {}s, stopping them",
+        unfinished.len(),
+        drain_timeout.as_secs()
+    );
+    for task in &unfinished {
+        task.handle.abort();
+    }
+    unfinished.len()
+}
+
+fn drain_timeout(config: &AppConfig) -> Duration {
+    Duration::from_secs(
+        config
+            .drain_timeout_seconds
+            .unwrap_or(DEFAULT_DRAIN_TIMEOUT_SECONDS),
+    )
+}
+
+/// Reloads the configuration. The sender, notifications and rules are replaced
+/// at once for the next batch of every task, and receivers that were added,
+/// changed or removed are started, restarted or stopped while the others keep
+/// running untouched. The tasks of stopped and restarted receivers move to
+/// `stopping`, so a shutdown still waits for them. An invalid configuration
+/// changes nothing.
+async fn reload(
+    path: Option<&str>,
+    current: &mut AppConfig,
+    receivers: &mut Vec<RunningReceiver>,
+    stopping: &mut Vec<Task>,
+    shared: &SharedContext,
+    outbound_tx: &watch::Sender<Outbound>,
+) {
+    let loaded = validation::load(path).and_then(|config| {
+        let issues = validation::validate(&config);
+        if issues.is_empty() {
+            Ok(config)
+        } else {
+            Err(issues)
+        }
+    });
+    let config = match loaded {
+        Ok(config) => config,
+        Err(issues) => {
+            error!("Invalid config, keeping the current one:");
+            for issue in &issues {
+                error!("  {}", issue);
+            }
+            return;
+        }
+    };
+    let sender = match SmtpSender::new(config.sender.clone()) {
+        Ok(sender) => sender,
+        Err(e) => {
+            error!("Invalid sender, keeping the current config: {:?}", e);
+            return;
+        }
+    };
+
+    // Always rebuilt, so a reload also picks up a rotated DKIM key
+    outbound_tx.send_replace(Outbound {
+        sender: Arc::new(sender),
+        rules: Arc::new(config.rules.clone()),
+        notifications: Arc::new(notifications::create_notifications(&config.notifications)),
+    });
+
+    if config.state_dir != current.state_dir
+        || config.spool != current.spool
+        || config.log_file != current.log_file
+        || config.log_level != current.log_level
+        || config.quiet != current.quiet
+    {
+        warn!("Changes to state_dir, spool and logging settings take effect after a restart");
+    }
+
+    let plan = plan_reload(
+        receivers
+            .iter()
+            .map(|receiver| (&receiver.settings, receiver.task.handle.is_finished())),
+        &config,
+    );
+    let mut running: HashMap<String, RunningReceiver> = receivers
+        .drain(..)
+        .map(|receiver| (receiver.settings.key(), receiver))
+        .collect();
+
+    // Stopped tasks are not waited for here, nor aborted halfway through a batch:
+    // a restarted receiver only starts polling once its old task has finished the
+    // batch and handed over the seen IDs
+    stopping.retain(|task| !task.handle.is_finished());
+    for key in &plan.removed {
+        if let Some(receiver) = running.remove(key) {
+            let _ = receiver.task.stop_tx.send(());
+            stopping.push(receiver.task);
+        }
+    }
+    let mut states: HashMap<String, ReceiverState> = HashMap::new();
+    for key in &plan.restarted {
+        if let Some(receiver) = running.remove(key) {
+            let _ = receiver.task.stop_tx.send(());
+            stopping.push(receiver.task);
+            states.insert(key.clone(), receiver.state);
+        }
+    }
+
+    for receiver_config in &config.receivers {
+        let key = StateStore::key(receiver_config);
+        let receiver = match running.remove(&key) {
+            Some(receiver) => receiver,
+            None => spawn_receiver(
+                ReceiverSettings::new(receiver_config, &config.forward_to),
+                states.remove(&key).unwrap_or_default(),
+                shared,
+                false,
+            ),
+        };
+        receivers.push(receiver);
+    }
+
+    info!(
+        "Config reloaded: {} receiver(s) unchanged, {} restarted, {} stopped, {} started",
+        plan.unchanged.len(),
+        plan.restarted.len(),
+        plan.removed.len(),
+        plan.added.len()
+    );
+    *current = config;
+}
+
+/// The outcome of `--once`: an error, and so a non-zero exit status, if any
+/// receiver cycle or spooled email failed.
+fn single_run_result(failed_receivers: usize, failed_spooled: usize) -> anyhow::Result<()> {
+    if failed_receivers > 0 || failed_spooled > 0 {
+        anyhow::bail!(
+            "{} receiver(s) and {} spooled email(s) failed",
+            failed_receivers,
+            failed_spooled
+        );
+    }
+    Ok(())
+}
+
+/// Polls the modification time of the config file for `--watch-config`.
+struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    ticker: tokio::time::Interval,
+}
+
+impl ConfigWatcher {
+    fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let mut ticker = tokio::time::interval(Duration::from_secs(CONFIG_WATCH_INTERVAL_SECONDS));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            modified: Self::modified(&path),
+            path,
+            ticker,
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Takes the current state of the file as seen, e.g. after a reload on SIGHUP.
+    fn refresh(&mut self) {
+        self.modified = Self::modified(&self.path);
+    }
+
+    /// Resolves once the file was modified, replaced or removed.
+    async fn changed(&mut self) {
+        loop {
+            self.ticker.tick().await;
+            if Self::modified(&self.path) != self.modified {
+                self.refresh();
+                return;
+            }
+        }
+    }
+}
+
+/// Waits for Ctrl+C, SIGTERM (as sent by `docker stop`) or SIGQUIT and returns
+/// the name of the signal.
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut quit = signal(SignalKind::quit())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result.map(|_| "SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+            _ = quit.recv() => Ok("SIGQUIT"),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.map(|_| "Ctrl+C")
 }
 
 #[tokio::main]
@@ -272,62 +1247,656 @@ async fn main() -> anyhow::Result<()> {
     let _ = crypto::ring::default_provider().install_default();
 
     let args = Args::parse();
+    let dry_run = args.dry_run;
+    // A dry run changes nothing, so polling again would only repeat the same report
+    let once = args.once || dry_run;
 
-    let config = match args.config {
-        Some(path) => AppConfig::new_from_file(&path),
-        None => AppConfig::new(),
-    }
-    .unwrap_or_else(|e| {
-        eprintln!("Failed to load config: {:?}", e);
-        if let Ok(path) = std::env::current_dir() {
-            eprintln!("Current search path: {:?}", path);
+    let mut config = load_config(args.config.as_deref());
+    match args.command {
+        Some(Command::CheckConfig) => {
+            println!(
+                "Config {} is valid: {} receiver(s), {} notification(s), {} rule(s)",
+                args.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH),
+                config.receivers.len(),
+                config.notifications.len(),
+                config.rules.len()
+            );
+            return Ok(());
         }
-        eprintln!("Please create a `config.toml` or set APP_... environment variables, or specify a config file with --config.");
-        std::process::exit(1);
-    });
+        Some(Command::TestConnection { json }) => {
+            let reports = connection_test::run(&config).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&reports)?);
+            } else {
+                for report in &reports {
+                    println!("{}", report);
+                }
+            }
+            if !reports.iter().all(|report| report.succeeded()) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::SrsReverse { address }) => {
+            let (Some(secret), Some(domain)) =
+                (&config.sender.srs_secret, config.sender.srs_domain())
+            else {
+                anyhow::bail!("SRS is not configured: set srs_secret in [sender]");
+            };
+            println!("{}", Srs::new(secret, domain).reverse(&address)?);
+            return Ok(());
+        }
+        None => {}
+    }
 
     initialize_logger(&config)?;
 
     info!("Starting Mail Forwarder...");
-    info!("Forwarding to: {}", config.forward_to);
+    if !config.forward_to.is_empty() {
+        info!("Forwarding to: {}", config.forward_to.join(", "));
+    }
+    if dry_run {
+        info!("Dry run: nothing will be sent, deleted or notified");
+    }
+
+    let (outbound_tx, outbound) = watch::channel(Outbound {
+        sender: Arc::new(SmtpSender::new(config.sender.clone())?),
+        rules: Arc::new(config.rules.clone()),
+        notifications: Arc::new(notifications::create_notifications(&config.notifications)),
+    });
+    let mut tasks = vec![];
+
+    let spool = match &config.spool {
+        // The spool is only ever written to, so a dry run leaves it alone
+        Some(_) if dry_run => None,
+        Some(spool_config) => {
+            let spool = Arc::new(Spool::open(spool_config)?);
+            info!("Queueing outbound emails in {}", spool_config.dir);
+            // With --once, due emails are delivered after the receivers are done
+            if !once {
+                let (stop_tx, stop_rx) = broadcast::channel(1);
+                let spool_task = run_spool_task(spool.clone(), outbound.clone(), stop_rx);
+                let handle = tokio::spawn(async move {
+                    spool_task.await;
+                    true
+                });
+                tasks.push(Task { stop_tx, handle });
+            }
+            Some(spool)
+        }
+        None => None,
+    };
+
+    let shared = SharedContext {
+        outbound,
+        spool,
+        state_dir: config.state_dir.clone(),
+        dry_run,
+    };
+
+    // Receivers without forward_to or rules were rejected by the validation
+    let mut receivers: Vec<RunningReceiver> = config
+        .receivers
+        .iter()
+        .map(|receiver_config| {
+            let settings = ReceiverSettings::new(receiver_config, &config.forward_to);
+            spawn_receiver(settings, ReceiverState::default(), &shared, once)
+        })
+        .collect();
+    // Tasks of receivers a reload stopped, possibly still finishing a batch
+    let mut stopping: Vec<Task> = vec![];
+
+    if once {
+        // Every receiver finishes its single cycle, notifications included
+        let mut failed_receivers = 0;
+        for receiver in receivers {
+            if !receiver.task.handle.await.unwrap_or(false) {
+                failed_receivers += 1;
+            }
+        }
+
+        let mut failed_spooled = 0;
+        if let Some(spool) = &shared.spool {
+            let current = shared.outbound.borrow().clone();
+            failed_spooled = deliver_spooled(spool, &current.sender, &current.notifications).await;
+        }
+
+        single_run_result(failed_receivers, failed_spooled)?;
+        info!("Single run finished. Goodbye!");
+        return Ok(());
+    }
+
+    let config_path = args.config.as_deref();
+    let mut watcher = args
+        .watch_config
+        .then(|| ConfigWatcher::new(config_path.unwrap_or(DEFAULT_CONFIG_PATH)));
+    #[cfg(unix)]
+    let mut hangups = signal::unix::signal(signal::unix::SignalKind::hangup())?;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        #[cfg(unix)]
+        let hangup = hangups.recv();
+        #[cfg(not(unix))]
+        let hangup = std::future::pending::<Option<()>>();
+        let file_changed = async {
+            match &mut watcher {
+                Some(watcher) => watcher.changed().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let trigger = tokio::select! {
+            result = &mut shutdown => {
+                match result {
+                    Ok(name) => warn!("Shutdown signal received ({}). Notifying tasks...", name),
+                    Err(err) => error!("Unable to listen for shutdown signal: {}", err),
+                }
+                break;
+            }
+            _ = hangup => "SIGHUP",
+            _ = file_changed => "config file changed",
+        };
+        info!("Reloading config ({})...", trigger);
+        reload(
+            config_path,
+            &mut config,
+            &mut receivers,
+            &mut stopping,
+            &shared,
+            &outbound_tx,
+        )
+        .await;
+        if let Some(watcher) = &mut watcher {
+            watcher.refresh();
+        }
+    }
+
+    tasks.extend(receivers.into_iter().map(|receiver| receiver.task));
+    tasks.extend(stopping);
+    let drain_timeout = drain_timeout(&config);
+    info!(
+        "Waiting up to {}s for {} tasks to finish...",
+        drain_timeout.as_secs(),
+        tasks.len()
+    );
+    let aborted = stop_tasks(tasks, drain_timeout).await;
+    if aborted > 0 {
+        anyhow::bail!(
+            "{} task(s) were aborted before finishing their batch",
+            aborted
+        );
+    }
+
+    info!("All tasks stopped. Goodbye!");
+    Ok(())
+}
 
-    let notifications = Arc::new(notifications::create_notifications(&config.notifications));
-    let sender = Arc::new(SmtpSender::new(config.sender.clone()));
-    let (shutdown_tx, _) = broadcast::channel(1);
-    let mut handles = vec![];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SenderConfig, SpoolConfig};
+    use ::config::{Config, File, FileFormat};
+    use smtp_sender::{MockSmtpMailer, MockSmtpMailerFactory};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use traits::{Email, MockMailReceiver, MockNotification};
 
-    for receiver_config in config.receivers {
-        let sender = sender.clone();
-        let forward_to = config.forward_to.clone();
-        let notifications = notifications.clone();
-        let shutdown_rx = shutdown_tx.subscribe();
+    const CONFIG: &str = r#"
+        forward_to = "target@example.com"
 
+        [sender]
+        host = "smtp.example.com"
+        port = 587
+        username = "u"
+        password = "p"
+
+        [[receivers]]
+        host = "pop.example.com"
+        port = 995
+        username = "a"
+        password = "p"
+        protocol = "pop3"
+
+        [[receivers]]
+        host = "imap.example.com"
+        port = 993
+        username = "b"
+        password = "p"
+        protocol = "imap"
+    "#;
+
+    fn parse(toml: &str) -> AppConfig {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn settings(config: &AppConfig) -> Vec<ReceiverSettings> {
+        config
+            .receivers
+            .iter()
+            .map(|receiver_config| ReceiverSettings::new(receiver_config, &config.forward_to))
+            .collect()
+    }
+
+    fn keys(config: &AppConfig) -> Vec<String> {
+        config.receivers.iter().map(StateStore::key).collect()
+    }
+
+    #[test]
+    fn test_reload_plan_unchanged() {
+        let config = parse(CONFIG);
+        let running = settings(&config);
+
+        let plan = plan_reload(running.iter().map(|s| (s, false)), &config);
+        assert_eq!(
+            plan,
+            ReloadPlan {
+                unchanged: keys(&config),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_reload_plan_restarts_changed_and_finished_receivers() {
+        let old = parse(CONFIG);
+        let running = settings(&old);
+
+        // A new interval, or a new forward_to, restarts the receiver
+        let new = parse(&CONFIG.replace(
+            "protocol = \"pop3\"",
+            "protocol = \"pop3\"\ncheck_interval_seconds = 600",
+        ));
+        let plan = plan_reload(running.iter().map(|s| (s, false)), &new);
+        assert_eq!(plan.unchanged, vec![keys(&old)[1].clone()]);
+        assert_eq!(plan.restarted, vec![keys(&old)[0].clone()]);
+
+        let new = parse(&CONFIG.replace("target@", "other@"));
+        let plan = plan_reload(running.iter().map(|s| (s, false)), &new);
+        assert_eq!(plan.restarted, keys(&old));
+
+        // A task that ended gets another try, even with the same settings
+        let plan = plan_reload(running.iter().zip([true, false]), &old);
+        assert_eq!(plan.unchanged, vec![keys(&old)[1].clone()]);
+        assert_eq!(plan.restarted, vec![keys(&old)[0].clone()]);
+        assert!(plan.removed.is_empty() && plan.added.is_empty());
+    }
+
+    #[test]
+    fn test_reload_plan_adds_and_removes_receivers() {
+        let old = parse(CONFIG);
+        let running = settings(&old);
+
+        let new = parse(&CONFIG.replace("username = \"b\"", "username = \"c\""));
+        let plan = plan_reload(running.iter().map(|s| (s, false)), &new);
+        assert_eq!(
+            plan,
+            ReloadPlan {
+                unchanged: vec![keys(&old)[0].clone()],
+                restarted: vec![],
+                removed: vec![keys(&old)[1].clone()],
+                added: vec![keys(&new)[1].clone()],
+            }
+        );
+    }
+
+    fn task(work: impl Future<Output = ()> + Send + 'static) -> Task {
+        let (stop_tx, mut stop_rx) = broadcast::channel(1);
         let handle = tokio::spawn(async move {
-            run_receiver_task(
-                receiver_config,
-                sender,
-                forward_to,
-                notifications,
-                shutdown_rx,
-            )
-            .await;
+            // Like a receiver, the stop signal is only seen between batches
+            let _ = stop_rx.recv().await;
+            work.await;
+            true
         });
+        Task { stop_tx, handle }
+    }
+
+    #[tokio::test]
+    async fn test_stop_tasks_drains_and_aborts() {
+        let batch_done = Arc::new(AtomicBool::new(false));
+        let done = batch_done.clone();
+        let finishing = task(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            done.store(true, Ordering::SeqCst);
+        });
+        let stuck = task(std::future::pending());
 
-        handles.push(handle);
+        assert_eq!(stop_tasks(vec![finishing], Duration::from_secs(5)).await, 0);
+        assert!(batch_done.load(Ordering::SeqCst));
+        assert_eq!(stop_tasks(vec![stuck], Duration::from_millis(50)).await, 1);
     }
 
-    match signal::ctrl_c().await {
-        Ok(()) => warn!("Shutdown signal received (Ctrl+C). Notifying tasks..."),
-        Err(err) => error!("Unable to listen for shutdown signal: {}", err),
+    fn email(id: &str) -> Email {
+        Email {
+            id: id.to_string(),
+            content: format!("From: a@example.org\r\nSubject: {}\r\n\r\nbody\r\n", id).into_bytes(),
+        }
     }
 
-    let _ = shutdown_tx.send(());
+    fn receiver_config() -> ReceiverConfig {
+        ReceiverConfig {
+            host: "pop.example.com".to_string(),
+            port: 995,
+            username: "a@example.com".to_string(),
+            password: "p".to_string(),
+            protocol: "pop3".to_string(),
+            delete_after_forward: Some(true),
+            imap_folder: "INBOX".to_string(),
+            ..Default::default()
+        }
+    }
 
-    info!("Waiting for {} tasks to finish...", handles.len());
-    for handle in handles {
-        let _ = handle.await;
+    /// A sender whose every delivery ends with `result`.
+    fn sender(result: fn() -> Result<(), SendError>) -> SmtpSender {
+        let mut factory = MockSmtpMailerFactory::new();
+        factory.expect_create().returning(move |_| {
+            let mut mailer = MockSmtpMailer::new();
+            mailer.expect_send().returning(move |_, _| result());
+            Ok(Box::new(mailer))
+        });
+        SmtpSender::new_with_factory(sender_config(), Arc::new(factory))
     }
 
-    info!("All tasks stopped. Goodbye!");
-    Ok(())
+    fn sender_config() -> SenderConfig {
+        SenderConfig {
+            host: "smtp.example.com".to_string(),
+            port: 465,
+            username: "sender@example.com".to_string(),
+            password: "p".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn forward_to() -> Vec<String> {
+        vec!["target@example.com".to_string()]
+    }
+
+    /// Processes `emails` like a receiver task does and returns the failure count.
+    async fn process(
+        sender: &SmtpSender,
+        receiver: &mut MockMailReceiver,
+        notifications: &[Box<dyn Notification>],
+        seen_ids: &mut StateStore,
+        dry_run: bool,
+        emails: Vec<Email>,
+    ) -> usize {
+        let config = receiver_config();
+        let filters = Filters::new(&config).unwrap();
+        let mut ctx = ProcessContext {
+            username: &config.username,
+            sender,
+            spool: None,
+            forward_to: &forward_to(),
+            rules: &[],
+            filters: &filters,
+            filter_action: FilterAction::Leave,
+            receiver,
+            seen_ids,
+            delete_after_forward: true,
+            notifications,
+            dry_run,
+        };
+        process_emails(&mut ctx, emails).await
+    }
+
+    #[tokio::test]
+    async fn test_transient_send_failure_fails_the_batch() {
+        let sender = sender(|| Err(SendError::transient("421 try again later")));
+        let mut receiver = MockMailReceiver::new();
+        receiver.expect_delete_emails().never();
+        let mut notification = MockNotification::new();
+        notification.expect_notify().never();
+        let notifications: Vec<Box<dyn Notification>> = vec![Box::new(notification)];
+        let mut seen_ids = StateStore::in_memory(&receiver_config());
+
+        let failures = process(
+            &sender,
+            &mut receiver,
+            &notifications,
+            &mut seen_ids,
+            false,
+            vec![email("1")],
+        )
+        .await;
+        assert_eq!(failures, 1);
+        // Left to be retried on the next check
+        assert!(!seen_ids.contains("1"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_failure_fails_the_batch() {
+        let sender = sender(|| Ok(()));
+        let mut receiver = MockMailReceiver::new();
+        receiver
+            .expect_delete_emails()
+            .times(1)
+            .returning(|_| Err(anyhow::anyhow!("connection reset")));
+        let mut notification = MockNotification::new();
+        notification.expect_notify().never();
+        let notifications: Vec<Box<dyn Notification>> = vec![Box::new(notification)];
+        let mut seen_ids = StateStore::in_memory(&receiver_config());
+
+        let failures = process(
+            &sender,
+            &mut receiver,
+            &notifications,
+            &mut seen_ids,
+            false,
+            vec![email("1"), email("2")],
+        )
+        .await;
+        assert_eq!(failures, 2);
+        // Forwarded, so not sent again when the deletion is retried
+        assert!(seen_ids.contains("1") && seen_ids.contains("2"));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_changes_nothing() {
+        let mut factory = MockSmtpMailerFactory::new();
+        factory.expect_create().never();
+        let sender = SmtpSender::new_with_factory(sender_config(), Arc::new(factory));
+        let mut receiver = MockMailReceiver::new();
+        receiver.expect_delete_emails().never();
+        receiver.expect_delete_email().never();
+        receiver.expect_mark_seen().never();
+        let mut notification = MockNotification::new();
+        notification.expect_notify().never();
+        notification.expect_notify_failure().never();
+        let notifications: Vec<Box<dyn Notification>> = vec![Box::new(notification)];
+        let mut seen_ids = StateStore::in_memory(&receiver_config());
+
+        let failures = process(
+            &sender,
+            &mut receiver,
+            &notifications,
+            &mut seen_ids,
+            true,
+            vec![email("1"), email("2")],
+        )
+        .await;
+        assert_eq!(failures, 0);
+        assert_eq!(seen_ids.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_email_without_default_is_filtered() {
+        let mut factory = MockSmtpMailerFactory::new();
+        factory.expect_create().never();
+        let sender = SmtpSender::new_with_factory(sender_config(), Arc::new(factory));
+        let mut receiver = MockMailReceiver::new();
+        receiver
+            .expect_mark_seen()
+            .withf(|ids| ids == ["1".to_string()])
+            .times(1)
+            .returning(|_| Ok(()));
+        receiver.expect_delete_emails().never();
+        let config = ReceiverConfig {
+            forward_to: None,
+            ..receiver_config()
+        };
+        let filters = Filters::new(&config).unwrap();
+        let rules = [RuleConfig {
+            subject: Some("[ALERT]".to_string()),
+            forward_to: Some(vec!["oncall@example.com".to_string()]),
+            ..Default::default()
+        }];
+        let mut seen_ids = StateStore::in_memory(&config);
+        let mut ctx = ProcessContext {
+            username: &config.username,
+            sender: &sender,
+            spool: None,
+            forward_to: &[],
+            rules: &rules,
+            filters: &filters,
+            filter_action: FilterAction::MarkSeen,
+            receiver: &mut receiver,
+            seen_ids: &mut seen_ids,
+            delete_after_forward: true,
+            notifications: &[],
+            dry_run: false,
+        };
+
+        assert_eq!(process_emails(&mut ctx, vec![email("1")]).await, 0);
+        // Not evaluated again on the next check
+        assert!(seen_ids.contains("1"));
+    }
+
+    fn shared(sender: SmtpSender, spool: Option<Arc<Spool>>) -> SharedContext {
+        let (_, outbound) = watch::channel(Outbound {
+            sender: Arc::new(sender),
+            rules: Arc::new(Vec::new()),
+            notifications: Arc::new(Vec::new()),
+        });
+        SharedContext {
+            outbound,
+            spool,
+            state_dir: None,
+            dry_run: false,
+        }
+    }
+
+    async fn run_once(receiver: MockMailReceiver) -> bool {
+        let (_stop_tx, stop_rx) = broadcast::channel(1);
+        run_receiver(
+            Box::new(receiver),
+            receiver_config(),
+            forward_to(),
+            ReceiverState::default(),
+            shared(sender(|| Ok(())), None),
+            true,
+            stop_rx,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_fetch_error_fails_the_run() {
+        let mut receiver = MockMailReceiver::new();
+        receiver
+            .expect_fetch_emails()
+            .times(1)
+            .returning(|_| Err(anyhow::anyhow!("login failed")));
+        assert!(!run_once(receiver).await);
+
+        let mut receiver = MockMailReceiver::new();
+        receiver
+            .expect_fetch_emails()
+            .times(1)
+            .returning(|_| Ok(vec![email("1")]));
+        receiver.expect_server_ids().return_const(None);
+        receiver
+            .expect_delete_emails()
+            .times(1)
+            .returning(|_| Ok(()));
+        assert!(run_once(receiver).await);
+    }
+
+    /// A receiver whose fetch asks to shut down and then outlasts the check
+    /// interval, like a slow batch during which SIGTERM arrives.
+    struct SlowReceiver {
+        fetches: usize,
+        stop_tx: broadcast::Sender<()>,
+    }
+
+    #[async_trait::async_trait]
+    impl MailReceiver for SlowReceiver {
+        async fn fetch_emails(&mut self, _: &HashSet<String>) -> anyhow::Result<Vec<Email>> {
+            self.fetches += 1;
+            assert_eq!(self.fetches, 1, "a batch started after shutdown");
+            let _ = self.stop_tx.send(());
+            tokio::time::sleep(Duration::from_secs(DEFAULT_CHECK_INTERVAL_SECONDS * 2)).await;
+            Ok(vec![])
+        }
+
+        async fn delete_email(&mut self, _: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn check_connection(&mut self) -> anyhow::Result<traits::MailboxStatus> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_during_a_batch_starts_no_other() {
+        // The tick missed during the batch is due along with the shutdown signal,
+        // over and over, as an unbiased select would pick either one
+        for _ in 0..20 {
+            let (stop_tx, stop_rx) = broadcast::channel(1);
+            let receiver = SlowReceiver {
+                fetches: 0,
+                stop_tx,
+            };
+            assert!(
+                run_receiver(
+                    Box::new(receiver),
+                    receiver_config(),
+                    forward_to(),
+                    ReceiverState::default(),
+                    shared(sender(|| Ok(())), None),
+                    false,
+                    stop_rx,
+                )
+                .await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spooled_failures_are_counted() {
+        let dir =
+            std::env::temp_dir().join(format!("mail-forwarder-once-spool-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let spool = Spool::open(&SpoolConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        spool
+            .enqueue("a@example.com", &email("1"), &forward_to())
+            .unwrap();
+
+        let failing = sender(|| Err(SendError::transient("421 try again later")));
+        assert_eq!(deliver_spooled(&spool, &failing, &[]).await, 1);
+
+        // The retry is not due yet
+        assert_eq!(deliver_spooled(&spool, &sender(|| Ok(())), &[]).await, 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_single_run_result() {
+        assert!(single_run_result(0, 0).is_ok());
+        assert_eq!(
+            single_run_result(1, 0).unwrap_err().to_string(),
+            "1 receiver(s) and 0 spooled email(s) failed"
+        );
+        assert!(single_run_result(0, 2).is_err());
+    }
 }
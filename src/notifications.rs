@@ -12,8 +12,9 @@
  * Last Modified: 2026-02-27 16:30:53
  */
 
-use crate::config::NotificationConfig;
-use crate::traits::{Email, Notification};
+use crate::config::{NotificationConfig, SecretCommand};
+use crate::credentials;
+use crate::traits::{Email, Notification, SendError};
 use async_trait::async_trait;
 use log::{error, info};
 use reqwest::Client;
@@ -26,15 +27,17 @@ use tokio::sync::Mutex;
 pub struct TelegramNotification {
     chat_id: String,
     token: String,
+    token_command: Option<SecretCommand>,
     client: Client,
     api_url: String,
 }
 
 impl TelegramNotification {
-    pub fn new(chat_id: String, token: String) -> Self {
+    pub fn new(chat_id: String, token: String, token_command: Option<SecretCommand>) -> Self {
         Self {
             chat_id,
             token,
+            token_command,
             client: Client::new(),
             api_url: "https://api.telegram.org".to_string(),
         }
@@ -45,21 +48,17 @@ impl TelegramNotification {
         Self {
             chat_id,
             token,
+            token_command: None,
             client: Client::new(),
             api_url,
         }
     }
-}
-
-#[async_trait]
-impl Notification for TelegramNotification {
-    async fn notify(&self, email: &Email, target_address: &str) -> anyhow::Result<()> {
-        let url = format!("{}/bot{}/sendMessage", self.api_url, self.token);
-        let message = format!(
-            "Email forwarded successfully!\nID: {}\nTarget: {}",
-            email.id, target_address
-        );
 
+    async fn send_message(&self, message: String) -> anyhow::Result<()> {
+        let account = format!("the Telegram bot of chat {}", self.chat_id);
+        let token =
+            credentials::resolve_async(&self.token, self.token_command.as_ref(), &account).await?;
+        let url = format!("{}/bot{}/sendMessage", self.api_url, token);
         let payload = serde_json::json!({
             "chat_id": self.chat_id,
             "text": message,
@@ -74,9 +73,41 @@ impl Notification for TelegramNotification {
             return Err(anyhow::anyhow!("Telegram API error: {}", status));
         }
 
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notification for TelegramNotification {
+    async fn notify(&self, email: &Email, target_addresses: &[String]) -> anyhow::Result<()> {
+        self.send_message(format!(
+            "Email forwarded successfully!\nID: {}\nTarget: {}",
+            email.id,
+            target_addresses.join(", ")
+        ))
+        .await?;
+
         info!("Telegram notification sent for email {}", email.id);
         Ok(())
     }
+
+    async fn notify_failure(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+        error: &SendError,
+    ) -> anyhow::Result<()> {
+        self.send_message(format!(
+            "Email could not be forwarded!\nID: {}\nTarget: {}\nError: {}",
+            email.id,
+            target_addresses.join(", "),
+            error
+        ))
+        .await?;
+
+        info!("Telegram failure notification sent for email {}", email.id);
+        Ok(())
+    }
 }
 
 /// A notification handler that appends a log entry to a local file.
@@ -93,11 +124,8 @@ impl FileNotification {
             lock: Arc::new(Mutex::new(())),
         }
     }
-}
 
-#[async_trait]
-impl Notification for FileNotification {
-    async fn notify(&self, email: &Email, target_address: &str) -> anyhow::Result<()> {
+    async fn append(&self, line: String) -> anyhow::Result<()> {
         let _guard = self.lock.lock().await;
         let mut file = OpenOptions::new()
             .create(true)
@@ -106,26 +134,58 @@ impl Notification for FileNotification {
             .await?;
 
         let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
-        let log_entry = format!(
-            "[{}] Forwarded email ID: {} to {}\n",
-            timestamp, email.id, target_address
-        );
+        let log_entry = format!("[{}] {}\n", timestamp, line);
 
         file.write_all(log_entry.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notification for FileNotification {
+    async fn notify(&self, email: &Email, target_addresses: &[String]) -> anyhow::Result<()> {
+        self.append(format!(
+            "Forwarded email ID: {} to {}",
+            email.id,
+            target_addresses.join(", ")
+        ))
+        .await?;
+
         info!("File notification written for email {}", email.id);
         Ok(())
     }
+
+    async fn notify_failure(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+        error: &SendError,
+    ) -> anyhow::Result<()> {
+        self.append(format!(
+            "Failed to forward email ID: {} to {}: {}",
+            email.id,
+            target_addresses.join(", "),
+            error
+        ))
+        .await?;
+
+        info!("File failure notification written for email {}", email.id);
+        Ok(())
+    }
 }
 
 use lettre::Message;
 use lettre::message::header::ContentType;
+use lettre::transport::smtp::AsyncSmtpTransportBuilder;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
 
 /// A notification handler that sends an email via SMTP.
 pub struct EmailNotification {
     smtp_username: String,
-    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    smtp_password: String,
+    smtp_password_command: Option<SecretCommand>,
+    relay: AsyncSmtpTransportBuilder,
 }
 
 impl EmailNotification {
@@ -134,37 +194,82 @@ impl EmailNotification {
         smtp_port: u16,
         smtp_username: String,
         smtp_password: String,
+        smtp_password_command: Option<SecretCommand>,
     ) -> anyhow::Result<Self> {
-        let creds = Credentials::new(smtp_username.clone(), smtp_password);
-        let mailer = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp_host)?
-            .port(smtp_port)
-            .credentials(creds)
-            .build();
+        let relay = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp_host)?.port(smtp_port);
 
         Ok(Self {
             smtp_username,
-            mailer,
+            smtp_password,
+            smtp_password_command,
+            relay,
         })
     }
-}
 
-#[async_trait]
-impl Notification for EmailNotification {
-    async fn notify(&self, email: &Email, target_address: &str) -> anyhow::Result<()> {
+    /// Builds the transport with the current password, which `smtp_password_command`
+    /// may change between notices.
+    async fn mailer(&self) -> anyhow::Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let account = format!("the notification mailbox {}", self.smtp_username);
+        let password = credentials::resolve_async(
+            &self.smtp_password,
+            self.smtp_password_command.as_ref(),
+            &account,
+        )
+        .await?;
+        let creds = Credentials::new(self.smtp_username.clone(), password);
+        Ok(self.relay.clone().credentials(creds).build())
+    }
+
+    async fn send_notice(&self, subject: String, body: String) -> anyhow::Result<()> {
         let email_message = Message::builder()
             .from(self.smtp_username.parse()?)
             .to(self.smtp_username.parse()?) // Send to self as notification
-            .subject(format!("Notification: Email {} forwarded", email.id))
+            .subject(subject)
             .header(ContentType::TEXT_PLAIN)
-            .body(format!(
+            .body(body)?;
+
+        self.mailer().await?.send(email_message).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notification for EmailNotification {
+    async fn notify(&self, email: &Email, target_addresses: &[String]) -> anyhow::Result<()> {
+        self.send_notice(
+            format!("Notification: Email {} forwarded", email.id),
+            format!(
                 "Email with ID {} was successfully forwarded to {}.",
-                email.id, target_address
-            ))?;
+                email.id,
+                target_addresses.join(", ")
+            ),
+        )
+        .await?;
 
-        self.mailer.send(email_message).await?;
         info!("Email notification sent for email {}", email.id);
         Ok(())
     }
+
+    async fn notify_failure(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+        error: &SendError,
+    ) -> anyhow::Result<()> {
+        self.send_notice(
+            format!("Notification: Email {} could not be forwarded", email.id),
+            format!(
+                "Email with ID {} could not be forwarded to {}: {}",
+                email.id,
+                target_addresses.join(", "),
+                error
+            ),
+        )
+        .await?;
+
+        info!("Email failure notification sent for email {}", email.id);
+        Ok(())
+    }
 }
 
 /// Factory function to create a list of notification handlers based on the provided configuration.
@@ -173,10 +278,16 @@ pub fn create_notifications(configs: &[NotificationConfig]) -> Vec<Box<dyn Notif
 
     for config in configs {
         match config {
-            NotificationConfig::Telegram { chat_id, token } => {
+            NotificationConfig::Telegram {
+                chat_id,
+                token,
+                token_command,
+                ..
+            } => {
                 notifications.push(Box::new(TelegramNotification::new(
                     chat_id.clone(),
                     token.clone(),
+                    token_command.clone(),
                 )));
             }
             NotificationConfig::File { file_path } => {
@@ -187,12 +298,15 @@ pub fn create_notifications(configs: &[NotificationConfig]) -> Vec<Box<dyn Notif
                 smtp_port,
                 smtp_username,
                 smtp_password,
+                smtp_password_command,
+                ..
             } => {
                 match EmailNotification::new(
                     smtp_host.clone(),
                     *smtp_port,
                     smtp_username.clone(),
                     smtp_password.clone(),
+                    smtp_password_command.clone(),
                 ) {
                     Ok(notification) => notifications.push(Box::new(notification)),
                     Err(e) => error!("Failed to create EmailNotification: {}", e),
@@ -225,7 +339,9 @@ mod tests {
             content: vec![],
         };
 
-        let result = notification.notify(&email, "target@example.com").await;
+        let result = notification
+            .notify(&email, &["target@example.com".to_string()])
+            .await;
         assert!(result.is_ok());
 
         // Verify file contents
@@ -236,12 +352,39 @@ mod tests {
         let _ = fs::remove_file(&file_path).await;
     }
 
+    #[tokio::test]
+    async fn test_file_failure_notification() {
+        let file_path = env::temp_dir().join("test_failure_notification.log");
+        let _ = fs::remove_file(&file_path).await;
+
+        let notification = FileNotification::new(file_path.to_str().unwrap().to_string());
+        let email = Email {
+            id: "test-email-456".to_string(),
+            content: vec![],
+        };
+        let error = SendError::from_reply(550, "5.1.1 User unknown", "550 5.1.1 User unknown");
+
+        let result = notification
+            .notify_failure(&email, &["target@example.com".to_string()], &error)
+            .await;
+        assert!(result.is_ok());
+
+        let contents = fs::read_to_string(&file_path).await.unwrap();
+        assert!(contents.contains(
+            "Failed to forward email ID: test-email-456 to target@example.com: 550 5.1.1 User unknown"
+        ));
+
+        let _ = fs::remove_file(&file_path).await;
+    }
+
     #[test]
     fn test_create_notifications() {
         let configs = vec![
             NotificationConfig::Telegram {
                 chat_id: "123".to_string(),
                 token: "abc".to_string(),
+                token_file: None,
+                token_command: None,
             },
             NotificationConfig::File {
                 file_path: "test.log".to_string(),
@@ -251,6 +394,8 @@ mod tests {
                 smtp_port: 587,
                 smtp_username: "user".to_string(),
                 smtp_password: "password".to_string(),
+                smtp_password_file: None,
+                smtp_password_command: None,
             },
         ];
 
@@ -279,9 +424,58 @@ mod tests {
             content: vec![],
         };
 
-        let result = notification.notify(&email, "target@example.com").await;
+        let result = notification
+            .notify(&email, &["target@example.com".to_string()])
+            .await;
+        assert!(result.is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_telegram_token_command() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/botCOMMAND_TOKEN/sendMessage")
+            .with_status(200)
+            .with_body(r#"{"ok":true}"#)
+            .create_async()
+            .await;
+
+        let mut notification = TelegramNotification::with_api_url(
+            "TEST_CHAT_ID".to_string(),
+            String::new(),
+            server.url(),
+        );
+        notification.token_command = Some(SecretCommand {
+            command: vec!["echo".to_string(), "COMMAND_TOKEN".to_string()],
+            ttl_seconds: None,
+        });
+
+        let email = Email {
+            id: "test-email-123".to_string(),
+            content: vec![],
+        };
+
+        let result = notification
+            .notify(&email, &["target@example.com".to_string()])
+            .await;
         assert!(result.is_ok());
         mock.assert_async().await;
+
+        // A failing command is reported without calling the API
+        notification.token_command = Some(SecretCommand {
+            command: vec!["false".to_string()],
+            ttl_seconds: None,
+        });
+        let error = notification
+            .notify(&email, &["target@example.com".to_string()])
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to get the secret of the Telegram bot of chat TEST_CHAT_ID")
+        );
     }
 
     #[tokio::test]
@@ -305,7 +499,9 @@ mod tests {
             content: vec![],
         };
 
-        let result = notification.notify(&email, "target@example.com").await;
+        let result = notification
+            .notify(&email, &["target@example.com".to_string()])
+            .await;
         assert!(result.is_err());
         mock.assert_async().await;
     }
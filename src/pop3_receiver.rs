@@ -12,10 +12,14 @@
  * Last Modified: 2026-02-27 16:30:59
  */
 
-use crate::config::ReceiverConfig;
-use crate::traits::{Email, MailReceiver};
+use crate::config::{ProxyConfig, ReceiverConfig, TlsMode};
+use crate::credentials;
+use crate::proxy;
+use crate::tls;
+use crate::traits::{ConnectError, ConnectStage, Email, MailReceiver, MailboxStatus};
 use async_trait::async_trait;
 use pop3::{Pop3Connection, Pop3ConnectionFactory, Pop3MessageInfo};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::sync::Arc;
 
 use std::collections::HashSet;
@@ -58,6 +62,139 @@ impl Pop3Client for RealPop3Client {
     }
 }
 
+/// A minimal POP3 client over an already established stream.
+///
+/// `rust-pop3-client` always opens its own TCP connection, so connections that
+/// have to go through a SOCKS5 proxy use this client instead.
+struct StreamPop3Client<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> StreamPop3Client<S> {
+    /// Wraps the stream and consumes the server greeting.
+    fn new(stream: S) -> anyhow::Result<Self> {
+        let mut client = Self {
+            stream: BufReader::new(stream),
+        };
+        client.read_status_line()?;
+        Ok(client)
+    }
+
+    /// Reads a line as raw bytes, without its line ending. Message bodies may be
+    /// in any 8-bit charset, so nothing but status lines is decoded.
+    fn read_line(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line)? == 0 {
+            return Err(anyhow::anyhow!("Connection closed by server"));
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        Ok(line)
+    }
+
+    fn read_status_line(&mut self) -> anyhow::Result<String> {
+        let line = String::from_utf8_lossy(&self.read_line()?).into_owned();
+        match line.strip_prefix("+OK") {
+            Some(rest) => Ok(rest.trim().to_string()),
+            None => Err(anyhow::anyhow!("{}", line)),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.read_status_line()
+    }
+
+    /// Reads a dot-terminated multi-line response, undoing dot-stuffing.
+    fn read_multi_line(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = self.read_line()?;
+            if line == b"." {
+                return Ok(lines);
+            }
+            if line.first() == Some(&b'.') {
+                line.remove(0);
+            }
+            lines.push(line);
+        }
+    }
+
+    fn login(&mut self, username: &str, password: &str) -> anyhow::Result<()> {
+        self.command(&format!("USER {}", username))?;
+        self.command(&format!("PASS {}", password))?;
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + Send + Sync> Pop3Client for StreamPop3Client<S> {
+    fn list(&mut self) -> anyhow::Result<Vec<Pop3MessageInfo>> {
+        self.command("LIST")
+            .map_err(|e| anyhow::anyhow!("List failed: {:?}", e))?;
+
+        self.read_multi_line()?
+            .iter()
+            .map(|line| {
+                let line = String::from_utf8_lossy(line);
+                let mut parts = line.split_whitespace();
+                match (
+                    parts.next().and_then(|id| id.parse().ok()),
+                    parts.next().and_then(|size| size.parse().ok()),
+                ) {
+                    (Some(message_id), Some(message_size)) => Ok(Pop3MessageInfo {
+                        message_id,
+                        message_size,
+                    }),
+                    _ => Err(anyhow::anyhow!("Malformed LIST line: {}", line)),
+                }
+            })
+            .collect()
+    }
+
+    fn get_unique_id(&mut self, seq_num: u32) -> anyhow::Result<String> {
+        let response = self
+            .command(&format!("UIDL {}", seq_num))
+            .map_err(|e| anyhow::anyhow!("UIDL failed: {:?}", e))?;
+
+        response
+            .split_whitespace()
+            .nth(1)
+            .map(|uid| uid.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Malformed UIDL response: {}", response))
+    }
+
+    fn retrieve(&mut self, seq_num: u32, content: &mut Vec<u8>) -> anyhow::Result<()> {
+        self.command(&format!("RETR {}", seq_num))
+            .map_err(|e| anyhow::anyhow!("Retrieve failed: {:?}", e))?;
+
+        for line in self.read_multi_line()? {
+            content.extend_from_slice(&line);
+            content.extend_from_slice(b"\r\n");
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, seq_num: u32) -> anyhow::Result<()> {
+        self.command(&format!("DELE {}", seq_num))
+            .map_err(|e| anyhow::anyhow!("Delete failed: {:?}", e))?;
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Drop for StreamPop3Client<S> {
+    fn drop(&mut self) {
+        // Deletions are only committed once the session ends with QUIT
+        let _ = self.command("QUIT");
+    }
+}
+
 #[cfg_attr(test, mockall::automock)]
 pub trait Pop3ClientFactory: Send + Sync {
     fn create(&self, config: &ReceiverConfig) -> anyhow::Result<Box<dyn Pop3Client>>;
@@ -65,23 +202,103 @@ pub trait Pop3ClientFactory: Send + Sync {
 
 pub struct RealPop3ClientFactory;
 
+impl RealPop3ClientFactory {
+    /// Connects through the SOCKS5 proxy, which `rust-pop3-client` cannot do itself.
+    fn create_proxied(
+        config: &ReceiverConfig,
+        proxy: &ProxyConfig,
+    ) -> anyhow::Result<Box<dyn Pop3Client>> {
+        let tcp_stream = proxy::connect_blocking(&config.host, config.port, proxy)
+            .map_err(|e| ConnectError::new(ConnectStage::Connect, e.to_string()))?;
+
+        match config.tls_mode() {
+            TlsMode::Implicit => {
+                let connector = native_tls::TlsConnector::new().map_err(|e| {
+                    ConnectError::new(ConnectStage::Tls, format!("TLS setup failed: {}", e))
+                })?;
+                let tls_stream = connector.connect(&config.host, tcp_stream).map_err(|e| {
+                    ConnectError::new(ConnectStage::Tls, format!("TLS connection failed: {}", e))
+                })?;
+                Self::login(Self::greet(tls_stream)?, config)
+            }
+            TlsMode::None => Self::login(Self::greet(tcp_stream)?, config),
+            TlsMode::Starttls => Err(starttls_unsupported()),
+        }
+    }
+
+    fn greet<S: Read + Write>(stream: S) -> anyhow::Result<StreamPop3Client<S>> {
+        StreamPop3Client::new(stream)
+            .map_err(|e| ConnectError::new(ConnectStage::Connect, e.to_string()).into())
+    }
+
+    fn login<S: Read + Write + Send + Sync + 'static>(
+        mut client: StreamPop3Client<S>,
+        config: &ReceiverConfig,
+    ) -> anyhow::Result<Box<dyn Pop3Client>> {
+        client
+            .login(&config.username, &password(config)?)
+            .map_err(|e| ConnectError::new(ConnectStage::Auth, format!("Login failed: {:?}", e)))?;
+        Ok(Box::new(client))
+    }
+}
+
+/// Runs `password_command` if one is set; we are on a blocking thread already.
+fn password(config: &ReceiverConfig) -> anyhow::Result<String> {
+    let account = format!("{} on {}", config.username, config.host);
+    credentials::resolve(&config.password, config.password_command.as_ref(), &account)
+        .map_err(|e| ConnectError::new(ConnectStage::Auth, e.to_string()).into())
+}
+
+fn starttls_unsupported() -> anyhow::Error {
+    ConnectError::new(
+        ConnectStage::Tls,
+        "STARTTLS is not supported for POP3, use tls_mode \"implicit\" or \"none\"",
+    )
+    .into()
+}
+
+/// `Pop3ConnectionFactory::with_custom_certs` connects and handshakes in one go: plain I/O
+/// errors come from the TCP connection, everything else from TLS.
+fn tls_connect_stage(error: &(dyn std::error::Error + 'static)) -> ConnectStage {
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if !e.get_ref().is_some_and(|inner| inner.is::<rustls::Error>()) => {
+            ConnectStage::Connect
+        }
+        _ => ConnectStage::Tls,
+    }
+}
+
 impl Pop3ClientFactory for RealPop3ClientFactory {
     fn create(&self, config: &ReceiverConfig) -> anyhow::Result<Box<dyn Pop3Client>> {
-        let mut client: Box<dyn Pop3Connection + Send + Sync> = if config.use_tls.unwrap_or(true) {
-            Box::new(
-                Pop3ConnectionFactory::new(&config.host, config.port)
-                    .map_err(|e| anyhow::anyhow!("TLS connection failed: {:?}", e))?,
-            )
-        } else {
-            Box::new(
-                Pop3ConnectionFactory::without_tls(&config.host, config.port)
-                    .map_err(|e| anyhow::anyhow!("Connection failed: {:?}", e))?,
-            )
+        if let Some(proxy) = config.proxy() {
+            return Self::create_proxied(config, &proxy);
+        }
+
+        let mut client: Box<dyn Pop3Connection + Send + Sync> = match config.tls_mode() {
+            TlsMode::Implicit => Box::new(
+                Pop3ConnectionFactory::with_custom_certs(
+                    &config.host,
+                    config.port,
+                    tls::root_store(),
+                )
+                .map_err(|e| {
+                    ConnectError::new(
+                        tls_connect_stage(e.as_ref()),
+                        format!("TLS connection failed: {:?}", e),
+                    )
+                })?,
+            ),
+            TlsMode::None => Box::new(
+                Pop3ConnectionFactory::without_tls(&config.host, config.port).map_err(|e| {
+                    ConnectError::new(ConnectStage::Connect, format!("Connection failed: {:?}", e))
+                })?,
+            ),
+            TlsMode::Starttls => return Err(starttls_unsupported()),
         };
 
         client
-            .login(&config.username, &config.password)
-            .map_err(|e| anyhow::anyhow!("Login failed: {:?}", e))?;
+            .login(&config.username, &password(config)?)
+            .map_err(|e| ConnectError::new(ConnectStage::Auth, format!("Login failed: {:?}", e)))?;
 
         Ok(Box::new(RealPop3Client { inner: client }))
     }
@@ -90,6 +307,7 @@ impl Pop3ClientFactory for RealPop3ClientFactory {
 pub struct Pop3Receiver {
     config: ReceiverConfig,
     factory: Arc<dyn Pop3ClientFactory>,
+    server_ids: Option<HashSet<String>>,
 }
 
 impl Pop3Receiver {
@@ -97,12 +315,17 @@ impl Pop3Receiver {
         Self {
             config,
             factory: Arc::new(RealPop3ClientFactory),
+            server_ids: None,
         }
     }
 
     #[cfg(test)]
     pub fn new_with_factory(config: ReceiverConfig, factory: Arc<dyn Pop3ClientFactory>) -> Self {
-        Self { config, factory }
+        Self {
+            config,
+            factory,
+            server_ids: None,
+        }
     }
 }
 
@@ -113,29 +336,40 @@ impl MailReceiver for Pop3Receiver {
         let factory = self.factory.clone();
         let seen_ids_clone = seen_ids.clone();
 
-        tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<Email>> {
-            let mut client = factory.create(&config)?;
-            let list = client.list()?;
-            let mut emails = Vec::new();
+        let (emails, server_ids) = tokio::task::spawn_blocking(
+            move || -> anyhow::Result<(Vec<Email>, HashSet<String>)> {
+                let mut client = factory.create(&config)?;
+                let list = client.list()?;
+                let mut emails = Vec::new();
+                let mut server_ids = HashSet::with_capacity(list.len());
 
-            for msg in list {
-                let uid = client
-                    .get_unique_id(msg.message_id)
-                    .unwrap_or_else(|_| msg.message_id.to_string());
+                for msg in list {
+                    let uid = client
+                        .get_unique_id(msg.message_id)
+                        .unwrap_or_else(|_| msg.message_id.to_string());
+
+                    server_ids.insert(uid.clone());
+                    if seen_ids_clone.contains(&uid) {
+                        continue;
+                    }
+
+                    let mut content = Vec::new();
+                    client.retrieve(msg.message_id, &mut content)?;
 
-                if seen_ids_clone.contains(&uid) {
-                    continue;
+                    emails.push(Email { id: uid, content });
                 }
 
-                let mut content = Vec::new();
-                client.retrieve(msg.message_id, &mut content)?;
+                Ok((emails, server_ids))
+            },
+        )
+        .await??;
 
-                emails.push(Email { id: uid, content });
-            }
+        self.server_ids = Some(server_ids);
+        Ok(emails)
+    }
 
-            Ok(emails)
-        })
-        .await?
+    fn server_ids(&self) -> Option<&HashSet<String>> {
+        self.server_ids.as_ref()
     }
 
     async fn delete_email(&mut self, id: &str) -> anyhow::Result<()> {
@@ -200,6 +434,24 @@ impl MailReceiver for Pop3Receiver {
         })
         .await?
     }
+
+    async fn check_connection(&mut self) -> anyhow::Result<MailboxStatus> {
+        let config = self.config.clone();
+        let factory = self.factory.clone();
+
+        tokio::task::spawn_blocking(move || -> anyhow::Result<MailboxStatus> {
+            let mut client = factory.create(&config)?;
+            let list = client
+                .list()
+                .map_err(|e| ConnectError::new(ConnectStage::Mailbox, e.to_string()))?;
+
+            Ok(MailboxStatus {
+                messages: list.len(),
+                unseen: None,
+            })
+        })
+        .await?
+    }
 }
 
 #[cfg(test)]
@@ -218,6 +470,7 @@ mod pop3_receiver_tests {
             check_interval_seconds: Some(60),
             delete_after_forward: Some(false),
             imap_folder: "INBOX".to_string(),
+            ..Default::default()
         }
     }
 
@@ -279,6 +532,55 @@ mod pop3_receiver_tests {
         assert_eq!(emails[1].id, "uid2");
     }
 
+    #[tokio::test]
+    async fn test_fetch_emails_reports_server_ids() {
+        let config = get_test_config();
+
+        let mut mock_factory = MockPop3ClientFactory::new();
+        mock_factory.expect_create().returning(|_| {
+            let mut mock_client = MockPop3Client::new();
+
+            mock_client.expect_list().returning(|| {
+                Ok(vec![
+                    Pop3MessageInfo {
+                        message_id: 1,
+                        message_size: 100,
+                    },
+                    Pop3MessageInfo {
+                        message_id: 2,
+                        message_size: 200,
+                    },
+                ])
+            });
+            mock_client
+                .expect_get_unique_id()
+                .returning(|seq| Ok(format!("uid{}", seq)));
+            mock_client
+                .expect_retrieve()
+                .with(eq(2), always())
+                .returning(|_, buf| {
+                    buf.extend_from_slice(b"email2");
+                    Ok(())
+                });
+
+            Ok(Box::new(mock_client))
+        });
+
+        let mut receiver = Pop3Receiver::new_with_factory(config, Arc::new(mock_factory));
+        assert!(receiver.server_ids().is_none());
+
+        let seen_ids = HashSet::from(["uid1".to_string()]);
+        let emails = receiver.fetch_emails(&seen_ids).await.unwrap();
+
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].id, "uid2");
+
+        let server_ids = receiver.server_ids().unwrap();
+        assert_eq!(server_ids.len(), 2);
+        assert!(server_ids.contains("uid1"));
+        assert!(server_ids.contains("uid2"));
+    }
+
     #[tokio::test]
     async fn test_delete_email_success() {
         let config = get_test_config();
@@ -315,6 +617,84 @@ mod pop3_receiver_tests {
             .expect("Delete should succeed");
     }
 
+    /// An in-memory stream that replays scripted server replies and records
+    /// everything the client writes.
+    struct ScriptedStream {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl Read for ScriptedStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for ScriptedStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_client_session() {
+        let output = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let stream = ScriptedStream {
+            input: std::io::Cursor::new(
+                concat!(
+                    "+OK POP3 ready\r\n",
+                    "+OK\r\n",
+                    "+OK logged in\r\n",
+                    "+OK 1 messages\r\n",
+                    "1 42\r\n",
+                    ".\r\n",
+                    "+OK 1 uid-abc\r\n",
+                    "+OK message follows\r\n",
+                    "Subject: Hi\r\n",
+                    "\r\n",
+                    "..dot-stuffed\r\n",
+                )
+                .as_bytes()
+                .iter()
+                // Latin-1 "café", which is not valid UTF-8
+                .chain(b"caf\xe9\r\n")
+                .chain(b".\r\n+OK deleted\r\n+OK bye\r\n")
+                .copied()
+                .collect(),
+            ),
+            output: output.clone(),
+        };
+
+        {
+            let mut client = StreamPop3Client::new(stream).unwrap();
+            client.login("user", "pass").unwrap();
+
+            let list = client.list().unwrap();
+            assert_eq!(list.len(), 1);
+            assert_eq!(list[0].message_id, 1);
+            assert_eq!(list[0].message_size, 42);
+
+            assert_eq!(client.get_unique_id(1).unwrap(), "uid-abc");
+
+            let mut content = Vec::new();
+            client.retrieve(1, &mut content).unwrap();
+            assert_eq!(content, b"Subject: Hi\r\n\r\n.dot-stuffed\r\ncaf\xe9\r\n");
+
+            client.delete(1).unwrap();
+        }
+
+        let commands = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            commands,
+            "USER user\r\nPASS pass\r\nLIST\r\nUIDL 1\r\nRETR 1\r\nDELE 1\r\nQUIT\r\n"
+        );
+    }
+
     #[tokio::test]
     async fn test_real_pop3_connection() {
         let _ = rustls::crypto::ring::default_provider().install_default();
@@ -354,4 +734,67 @@ mod pop3_receiver_tests {
 
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_check_connection_counts_messages() {
+        let mut mock_factory = MockPop3ClientFactory::new();
+        mock_factory.expect_create().returning(|_| {
+            let mut mock_client = MockPop3Client::new();
+            mock_client.expect_list().returning(|| {
+                Ok((1..=3)
+                    .map(|message_id| Pop3MessageInfo {
+                        message_id,
+                        message_size: 100,
+                    })
+                    .collect())
+            });
+            // Nothing is retrieved or deleted
+            mock_client.expect_retrieve().never();
+            mock_client.expect_delete().never();
+            Ok(Box::new(mock_client))
+        });
+
+        let mut receiver =
+            Pop3Receiver::new_with_factory(get_test_config(), Arc::new(mock_factory));
+        let status = receiver.check_connection().await.unwrap();
+
+        assert_eq!(
+            status,
+            MailboxStatus {
+                messages: 3,
+                unseen: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_connection_reports_failed_stage() {
+        let mut mock_factory = MockPop3ClientFactory::new();
+        mock_factory.expect_create().returning(|_| {
+            Err(ConnectError::new(ConnectStage::Auth, "Login failed: -ERR invalid password").into())
+        });
+
+        let mut receiver =
+            Pop3Receiver::new_with_factory(get_test_config(), Arc::new(mock_factory));
+        let error = receiver.check_connection().await.unwrap_err();
+
+        assert_eq!(ConnectError::stage_of(&error), ConnectStage::Auth);
+        assert_eq!(error.to_string(), "Login failed: -ERR invalid password");
+    }
+
+    #[test]
+    fn test_tls_connect_stage() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(tls_connect_stage(&refused), ConnectStage::Connect);
+
+        let handshake = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::General("bad certificate".to_string()),
+        );
+        assert_eq!(tls_connect_stage(&handshake), ConnectStage::Tls);
+        assert_eq!(
+            tls_connect_stage(&rustls::Error::General("no roots".to_string())),
+            ConnectStage::Tls
+        );
+    }
 }
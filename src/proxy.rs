@@ -0,0 +1,219 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: proxy.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 14:02:51
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 14:02:51
+ */
+
+use crate::config::ProxyConfig;
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+/// Opens a TCP connection to `host:port`, tunneled through the SOCKS5 proxy if one is given.
+///
+/// The target host name is resolved by the proxy, so servers only reachable from the
+/// proxy's network work as expected.
+pub async fn connect(
+    host: &str,
+    port: u16,
+    proxy: Option<&ProxyConfig>,
+) -> anyhow::Result<TcpStream> {
+    let Some(proxy) = proxy else {
+        let addr = format!("{}:{}", host, port);
+        return TcpStream::connect(&addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", addr, e));
+    };
+
+    let stream = match proxy.credentials() {
+        Some((username, password)) => {
+            Socks5Stream::connect_with_password(
+                proxy.address.as_str(),
+                (host, port),
+                username,
+                password,
+            )
+            .await
+        }
+        None => Socks5Stream::connect(proxy.address.as_str(), (host, port)).await,
+    }
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to connect to {}:{} via SOCKS5 proxy {}: {}",
+            host,
+            port,
+            proxy.address,
+            e
+        )
+    })?;
+
+    Ok(stream.into_inner())
+}
+
+/// Blocking variant of [`connect`] for the POP3 client, which runs on a blocking thread.
+/// Always goes through the proxy.
+pub fn connect_blocking(
+    host: &str,
+    port: u16,
+    proxy: &ProxyConfig,
+) -> anyhow::Result<std::net::TcpStream> {
+    let stream = match proxy.credentials() {
+        Some((username, password)) => socks::Socks5Stream::connect_with_password(
+            proxy.address.as_str(),
+            (host, port),
+            username,
+            password,
+        ),
+        None => socks::Socks5Stream::connect(proxy.address.as_str(), (host, port)),
+    }
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to connect to {}:{} via SOCKS5 proxy {}: {}",
+            host,
+            port,
+            proxy.address,
+            e
+        )
+    })?;
+
+    Ok(stream.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Starts a SOCKS5 stand-in that accepts one CONNECT request, optionally requiring
+    /// username/password authentication, then greets the client with the requested target.
+    async fn spawn_socks5_stand_in(
+        credentials: Option<(&'static str, &'static str)>,
+    ) -> (String, tokio::task::JoinHandle<()>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // Method negotiation
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut methods = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+
+            if let Some((username, password)) = credentials {
+                assert!(methods.contains(&2), "client did not offer password auth");
+                stream.write_all(&[5, 2]).await.unwrap();
+
+                let mut version_and_len = [0u8; 2];
+                stream.read_exact(&mut version_and_len).await.unwrap();
+                let mut user = vec![0u8; version_and_len[1] as usize];
+                stream.read_exact(&mut user).await.unwrap();
+                let mut pass_len = [0u8; 1];
+                stream.read_exact(&mut pass_len).await.unwrap();
+                let mut pass = vec![0u8; pass_len[0] as usize];
+                stream.read_exact(&mut pass).await.unwrap();
+
+                let accepted = user == username.as_bytes() && pass == password.as_bytes();
+                stream.write_all(&[1, u8::from(!accepted)]).await.unwrap();
+                if !accepted {
+                    return;
+                }
+            } else {
+                stream.write_all(&[5, 0]).await.unwrap();
+            }
+
+            // CONNECT request with a domain name target
+            let mut request = [0u8; 5];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..4], [5, 1, 0, 3]);
+            let mut host = vec![0u8; request[4] as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port).await.unwrap();
+
+            stream
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+
+            let greeting = format!(
+                "hello {}:{}\n",
+                String::from_utf8_lossy(&host),
+                u16::from_be_bytes(port)
+            );
+            stream.write_all(greeting.as_bytes()).await.unwrap();
+        });
+
+        (address, handle)
+    }
+
+    fn proxy_config(address: String, credentials: Option<(&str, &str)>) -> ProxyConfig {
+        ProxyConfig {
+            address,
+            username: credentials.map(|(u, _)| u.to_string()),
+            password: credentials.map(|(_, p)| p.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy() {
+        let (address, stand_in) = spawn_socks5_stand_in(None).await;
+        let proxy = proxy_config(address, None);
+
+        let mut stream = connect("mail.internal", 993, Some(&proxy)).await.unwrap();
+        let mut greeting = String::new();
+        stream.read_to_string(&mut greeting).await.unwrap();
+
+        assert_eq!(greeting, "hello mail.internal:993\n");
+        stand_in.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy_with_credentials() {
+        let (address, stand_in) = spawn_socks5_stand_in(Some(("bastion", "secret"))).await;
+        let proxy = proxy_config(address, Some(("bastion", "secret")));
+
+        let mut stream = connect("smtp.internal", 587, Some(&proxy)).await.unwrap();
+        let mut greeting = String::new();
+        stream.read_to_string(&mut greeting).await.unwrap();
+
+        assert_eq!(greeting, "hello smtp.internal:587\n");
+        stand_in.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy_wrong_credentials() {
+        let (address, stand_in) = spawn_socks5_stand_in(Some(("bastion", "secret"))).await;
+        let proxy = proxy_config(address, Some(("bastion", "wrong")));
+
+        assert!(connect("smtp.internal", 587, Some(&proxy)).await.is_err());
+        stand_in.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_blocking_through_proxy() {
+        let (address, stand_in) = spawn_socks5_stand_in(Some(("bastion", "secret"))).await;
+        let proxy = proxy_config(address, Some(("bastion", "secret")));
+
+        let greeting = tokio::task::spawn_blocking(move || {
+            let stream = connect_blocking("pop.internal", 995, &proxy).unwrap();
+            let mut greeting = String::new();
+            BufReader::new(stream).read_line(&mut greeting).unwrap();
+            greeting
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(greeting, "hello pop.internal:995\n");
+        stand_in.await.unwrap();
+    }
+}
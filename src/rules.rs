@@ -0,0 +1,270 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: rules.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 16:20:37
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 16:20:37
+ */
+
+use crate::config::RuleConfig;
+use mail_parser::{Address, Message, MessageParser};
+
+/// The outcome of evaluating the routing rules against an email.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Forward the email to these addresses.
+    Forward(Vec<String>),
+    /// A matching rule asked not to forward the email.
+    Skip,
+}
+
+/// Evaluates `rules` in order against the headers of a raw RFC 5322 message.
+///
+/// Every matching rule adds its `forward_to` addresses; a matching `skip` rule
+/// drops the email and a matching `stop` rule ends the evaluation. If no
+/// matching rule provides a destination, the email goes to `default`.
+pub fn route(rules: &[RuleConfig], raw: &[u8], default: &[String]) -> Route {
+    if rules.is_empty() {
+        return Route::Forward(default.to_vec());
+    }
+
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return Route::Forward(default.to_vec());
+    };
+
+    let mut destinations: Vec<String> = Vec::new();
+    for rule in rules {
+        if !matches(rule, &message) {
+            continue;
+        }
+
+        if rule.skip {
+            return Route::Skip;
+        }
+
+        for address in rule.forward_to.iter().flatten() {
+            if !destinations.contains(address) {
+                destinations.push(address.clone());
+            }
+        }
+
+        if rule.stop {
+            break;
+        }
+    }
+
+    if destinations.is_empty() {
+        Route::Forward(default.to_vec())
+    } else {
+        Route::Forward(destinations)
+    }
+}
+
+/// Checks whether every condition set on the rule matches the message.
+fn matches(rule: &RuleConfig, message: &Message) -> bool {
+    if let Some(pattern) = &rule.from
+        && !any_address_matches(message.from(), pattern)
+    {
+        return false;
+    }
+
+    if let Some(pattern) = &rule.to
+        && !any_address_matches(message.to(), pattern)
+        && !any_address_matches(message.cc(), pattern)
+    {
+        return false;
+    }
+
+    if let Some(needle) = &rule.subject {
+        let subject = message.subject().unwrap_or_default().to_lowercase();
+        if !subject.contains(&needle.to_lowercase()) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Checks whether any address in the header matches the glob, ignoring case.
+pub fn any_address_matches(address: Option<&Address>, pattern: &str) -> bool {
+    address.is_some_and(|address| {
+        address
+            .iter()
+            .filter_map(|addr| addr.address())
+            .any(|addr| glob_match(&pattern.to_lowercase(), &addr.to_lowercase()))
+    })
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` and `?`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVOICE: &[u8] = b"From: Billing <invoices@billing.vendor.com>\r\n\
+To: me@example.com\r\n\
+Subject: Your invoice for March\r\n\
+Message-ID: <1@billing.vendor.com>\r\n\
+\r\n\
+Please find your invoice attached.\r\n";
+
+    const ALERT: &[u8] = b"From: monitor@example.net\r\n\
+To: Ops Team <ops@example.com>\r\n\
+Cc: alias-x@example.com\r\n\
+Subject: [ALERT] disk usage above 90%\r\n\
+\r\n\
+/var is almost full.\r\n";
+
+    fn rule(from: Option<&str>, to: Option<&str>, subject: Option<&str>) -> RuleConfig {
+        RuleConfig {
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+            subject: subject.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn forward(rule: RuleConfig, addresses: &[&str]) -> RuleConfig {
+        RuleConfig {
+            forward_to: Some(addresses.iter().map(|a| a.to_string()).collect()),
+            ..rule
+        }
+    }
+
+    fn default_to() -> Vec<String> {
+        vec!["default@example.com".to_string()]
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(
+            "*@billing.vendor.com",
+            "invoices@billing.vendor.com"
+        ));
+        assert!(glob_match("alias-?@example.com", "alias-x@example.com"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("*@vendor.com", "invoices@billing.vendor.co"));
+        assert!(!glob_match("exact@example.com", "exact@example.com.evil"));
+    }
+
+    #[test]
+    fn test_no_rules_uses_default() {
+        assert_eq!(
+            route(&[], INVOICE, &default_to()),
+            Route::Forward(default_to())
+        );
+    }
+
+    #[test]
+    fn test_route_by_from() {
+        let rules = vec![forward(
+            rule(Some("*@Billing.Vendor.com"), None, None),
+            &["finance@example.com"],
+        )];
+
+        assert_eq!(
+            route(&rules, INVOICE, &default_to()),
+            Route::Forward(vec!["finance@example.com".to_string()])
+        );
+        assert_eq!(
+            route(&rules, ALERT, &default_to()),
+            Route::Forward(default_to())
+        );
+    }
+
+    #[test]
+    fn test_route_by_subject_and_cc() {
+        let rules = vec![
+            forward(rule(None, None, Some("[alert]")), &["oncall@example.com"]),
+            forward(
+                rule(None, Some("alias-x@example.com"), None),
+                &["y@example.com"],
+            ),
+        ];
+
+        assert_eq!(
+            route(&rules, ALERT, &default_to()),
+            Route::Forward(vec![
+                "oncall@example.com".to_string(),
+                "y@example.com".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_all_conditions_must_match() {
+        let rules = vec![forward(
+            rule(Some("monitor@example.net"), None, Some("invoice")),
+            &["nobody@example.com"],
+        )];
+
+        assert_eq!(
+            route(&rules, ALERT, &default_to()),
+            Route::Forward(default_to())
+        );
+    }
+
+    #[test]
+    fn test_stop_and_skip() {
+        let stop = RuleConfig {
+            stop: true,
+            ..forward(rule(None, None, Some("[ALERT]")), &["oncall@example.com"])
+        };
+        let catch_all = forward(RuleConfig::default(), &["archive@example.com"]);
+
+        assert_eq!(
+            route(&[stop, catch_all.clone()], ALERT, &default_to()),
+            Route::Forward(vec!["oncall@example.com".to_string()])
+        );
+        assert_eq!(
+            route(std::slice::from_ref(&catch_all), ALERT, &default_to()),
+            Route::Forward(vec!["archive@example.com".to_string()])
+        );
+
+        let skip = RuleConfig {
+            skip: true,
+            ..rule(Some("*@billing.vendor.com"), None, None)
+        };
+        assert_eq!(
+            route(&[skip, catch_all], INVOICE, &default_to()),
+            Route::Skip
+        );
+    }
+}
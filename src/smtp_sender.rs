@@ -12,34 +12,238 @@
  * Last Modified: 2026-02-27 16:31:17
  */
 
-use crate::config::SenderConfig;
-use crate::traits::{Email, MailSender};
+use crate::config::{
+    ForwardMode, HeaderMode, ProxyConfig, SMTP_TIMEOUT_SECONDS, SenderConfig, SmtpTlsMode,
+};
+use crate::credentials;
+use crate::dkim::{ArcSealer, DkimSigner};
+use crate::proxy;
+use crate::srs::{self, Srs};
+use crate::tls;
+use crate::traits::{ConnectError, ConnectStage, Email, MailSender, SendError};
 use async_trait::async_trait;
 use lettre::address::Envelope;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::transport::smtp::client::{Tls, TlsParameters};
-use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
-use std::sync::Arc;
-use tokio::sync::OnceCell;
+use lettre::message::header::{ContentDisposition, ContentTransferEncoding, ContentType};
+use lettre::message::{Body, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::{Credentials, DEFAULT_MECHANISMS};
+use lettre::transport::smtp::client::{AsyncSmtpConnection, AsyncTokioStream, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{Address, Message};
+use log::warn;
+use mail_parser::MessageParser;
+use rustls::pki_types::ServerName;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait SmtpMailer: Send + Sync {
-    async fn send(&self, envelope: Envelope, email: &[u8]) -> anyhow::Result<()>;
+    async fn send(&self, envelope: Envelope, email: &[u8]) -> Result<(), SendError>;
 }
 
+/// Classifies a lettre error: negative SMTP replies by their code, client-side
+/// problems as permanent and everything else (network, TLS, ...) as transient.
+/// Only meant for replies about the message itself, see [`session_error`].
+fn smtp_error(context: &str, error: lettre::transport::smtp::Error) -> SendError {
+    let message = format!("{}: {}", context, error);
+
+    match error.status() {
+        Some(code) => {
+            let text = std::error::Error::source(&error)
+                .map(|source| source.to_string())
+                .unwrap_or_default();
+            SendError::from_reply(u16::from(code), &text, message)
+        }
+        None if error.is_client() => SendError::permanent(message),
+        None => SendError::transient(message),
+    }
+}
+
+/// Classifies a failure to open the session, e.g. a 535 to AUTH or a 554
+/// greeting, as transient whatever the reply code: it says nothing about the
+/// message, and a fixed password or server should let it through later.
+fn session_error(context: &str, error: lettre::transport::smtp::Error) -> SendError {
+    smtp_error(context, error).into_transient()
+}
+
+/// A TLS stream over a tunneled TCP connection, in the shape lettre expects.
+#[derive(Debug)]
+struct TunneledTlsStream(tokio_rustls::client::TlsStream<TcpStream>);
+
+impl AsyncRead for TunneledTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TunneledTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+impl AsyncTokioStream for TunneledTlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.get_ref().0.peer_addr()
+    }
+}
+
+/// Opens an SMTP session with `config.host`, directly or through `proxy`, up to
+/// and including authentication, upgrading with `starttls` if the TLS mode asks
+/// for it. Returns whether the session is encrypted, or the step that failed
+/// with the error.
+async fn open_session(
+    config: &SenderConfig,
+    proxy: Option<&ProxyConfig>,
+    starttls: &TlsParameters,
+) -> Result<(AsyncSmtpConnection, bool), (ConnectStage, SendError)> {
+    let tcp_stream = proxy::connect(&config.host, config.port, proxy)
+        .await
+        .map_err(|e| (ConnectStage::Connect, SendError::transient(e.to_string())))?;
+    let hello = ClientId::default();
+    let tls_mode = config.tls_mode();
+
+    let stream: Box<dyn AsyncTokioStream> = if tls_mode == SmtpTlsMode::Implicit {
+        let server_name = ServerName::try_from(config.host.clone()).map_err(|e| {
+            (
+                ConnectStage::Tls,
+                SendError::transient(format!("Invalid TLS parameters: {}", e)),
+            )
+        })?;
+
+        let tls_stream = tokio_rustls::TlsConnector::from(tls::client_config())
+            .connect(server_name, tcp_stream)
+            .await
+            .map_err(|e| {
+                (
+                    ConnectStage::Tls,
+                    SendError::transient(format!("TLS connection failed: {}", e)),
+                )
+            })?;
+        Box::new(TunneledTlsStream(tls_stream))
+    } else {
+        Box::new(tcp_stream)
+    };
+
+    let mut connection = AsyncSmtpConnection::connect_with_transport(stream, &hello)
+        .await
+        .map_err(|e| {
+            (
+                ConnectStage::Connect,
+                session_error("SMTP connection failed", e),
+            )
+        })?;
+
+    let upgrade = match tls_mode {
+        SmtpTlsMode::Starttls => true,
+        SmtpTlsMode::Opportunistic => connection.can_starttls(),
+        SmtpTlsMode::Implicit | SmtpTlsMode::None => false,
+    };
+    if upgrade {
+        connection
+            .starttls(starttls.clone(), &hello)
+            .await
+            .map_err(|e| (ConnectStage::Tls, session_error("STARTTLS failed", e)))?;
+    }
+
+    let password = password(config)
+        .await
+        .map_err(|e| (ConnectStage::Auth, SendError::transient(e.to_string())))?;
+    let creds = Credentials::new(config.username.clone(), password);
+    connection
+        .auth(DEFAULT_MECHANISMS, &creds)
+        .await
+        .map_err(|e| {
+            (
+                ConnectStage::Auth,
+                session_error("SMTP authentication failed", e),
+            )
+        })?;
+
+    Ok((connection, upgrade || tls_mode == SmtpTlsMode::Implicit))
+}
+
+/// Opens a session with the SMTP server and logs in without sending anything,
+/// returning whether the session is encrypted.
+pub async fn test_connection(config: &SenderConfig) -> Result<bool, ConnectError> {
+    let starttls = tls::starttls_parameters(&config.host).map_err(|e| {
+        ConnectError::new(ConnectStage::Tls, format!("Invalid TLS parameters: {}", e))
+    })?;
+    let (mut connection, encrypted) = open_session(config, config.proxy().as_ref(), &starttls)
+        .await
+        .map_err(|(stage, e)| ConnectError::new(stage, e.to_string()))?;
+    let _ = connection.quit().await;
+    Ok(encrypted)
+}
+
+/// Returns the sender's password, running `password_command` if one is set.
+async fn password(config: &SenderConfig) -> anyhow::Result<String> {
+    let account = format!("sender {} on {}", config.username, config.host);
+    credentials::resolve_async(&config.password, config.password_command.as_ref(), &account).await
+}
+
+/// Sends each email in a session of its own, directly or through a SOCKS5 proxy.
+/// The session is driven by hand rather than with `AsyncSmtpTransport`, which
+/// cannot tunnel and does not tell a failed login from a rejected message.
 struct RealSmtpMailer {
-    transport: AsyncSmtpTransport<Tokio1Executor>,
+    config: SenderConfig,
+    proxy: Option<ProxyConfig>,
+    /// Built once, as lettre turns them into new rustls settings every time.
+    starttls: TlsParameters,
+}
+
+impl RealSmtpMailer {
+    async fn send_in_session(&self, envelope: &Envelope, email: &[u8]) -> Result<(), SendError> {
+        let (mut connection, _) = open_session(&self.config, self.proxy.as_ref(), &self.starttls)
+            .await
+            .map_err(|(_, e)| e)?;
+
+        let result = connection
+            .send(envelope, email)
+            .await
+            .map(|_| ())
+            .map_err(|e| smtp_error("SMTP send failed", e));
+
+        let _ = connection.quit().await;
+        result
+    }
 }
 
 #[async_trait]
 impl SmtpMailer for RealSmtpMailer {
-    async fn send(&self, envelope: Envelope, email: &[u8]) -> anyhow::Result<()> {
-        self.transport
-            .send_raw(&envelope, email)
+    async fn send(&self, envelope: Envelope, email: &[u8]) -> Result<(), SendError> {
+        let timeout = Duration::from_secs(SMTP_TIMEOUT_SECONDS);
+        tokio::time::timeout(timeout, self.send_in_session(&envelope, email))
             .await
-            .map_err(|e| anyhow::anyhow!("SMTP send failed: {}", e))?;
-        Ok(())
+            .unwrap_or_else(|_| {
+                Err(SendError::transient(format!(
+                    "SMTP session timed out after {} seconds",
+                    SMTP_TIMEOUT_SECONDS
+                )))
+            })
     }
 }
 
@@ -52,23 +256,12 @@ pub struct RealSmtpMailerFactory;
 
 impl SmtpMailerFactory for RealSmtpMailerFactory {
     fn create(&self, config: &SenderConfig) -> anyhow::Result<Box<dyn SmtpMailer>> {
-        let creds = Credentials::new(config.username.clone(), config.password.clone());
-        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
-            .map_err(|e| anyhow::anyhow!("Invalid SMTP host: {}", e))?
-            .port(config.port)
-            .credentials(creds);
-
-        if config.use_tls.unwrap_or(true) {
-            let tls_params = TlsParameters::new(config.host.clone())
-                .map_err(|e| anyhow::anyhow!("Invalid TLS parameters: {}", e))?;
-            builder = builder.tls(Tls::Wrapper(tls_params));
-        } else {
-            builder = builder.tls(Tls::None);
-        }
-
-        let transport = builder.build();
-
-        Ok(Box::new(RealSmtpMailer { transport }))
+        Ok(Box::new(RealSmtpMailer {
+            config: config.clone(),
+            proxy: config.proxy(),
+            starttls: tls::starttls_parameters(&config.host)
+                .map_err(|e| anyhow::anyhow!("Invalid TLS parameters: {}", e))?,
+        }))
     }
 }
 
@@ -76,70 +269,374 @@ impl SmtpMailerFactory for RealSmtpMailerFactory {
 #[path = "./smtp_sender_tests.rs"]
 mod smtp_sender_tests;
 
+/// What forwarding an email would send, as reported by dry runs.
+pub struct ForwardPreview {
+    /// The envelope sender, `None` for the null reverse path of a forwarded bounce.
+    pub envelope_from: Option<String>,
+    /// Names of the header fields added in front of the original email, or of
+    /// the new email's header fields when it is wrapped as an attachment.
+    pub added_headers: Vec<String>,
+    /// Whether the original is wrapped as an attachment of a new email.
+    pub attached: bool,
+}
+
 pub struct SmtpSender {
     config: SenderConfig,
     factory: Arc<dyn SmtpMailerFactory>,
-    mailer: OnceCell<Box<dyn SmtpMailer>>,
+    /// The mailer, with the password it was created with.
+    mailer: Mutex<Option<(String, Arc<dyn SmtpMailer>)>>,
+    dkim: Option<DkimSigner>,
+    arc: Option<ArcSealer>,
 }
 
 impl SmtpSender {
-    pub fn new(config: SenderConfig) -> Self {
-        Self {
-            config,
-            factory: Arc::new(RealSmtpMailerFactory),
-            mailer: OnceCell::new(),
-        }
+    /// Creates the sender, failing if the DKIM or ARC key cannot be loaded.
+    pub fn new(config: SenderConfig) -> anyhow::Result<Self> {
+        Self::with_factory(config, Arc::new(RealSmtpMailerFactory))
     }
 
     #[cfg(test)]
     pub fn new_with_factory(config: SenderConfig, factory: Arc<dyn SmtpMailerFactory>) -> Self {
-        Self {
+        Self::with_factory(config, factory).expect("invalid test sender configuration")
+    }
+
+    fn with_factory(
+        config: SenderConfig,
+        factory: Arc<dyn SmtpMailerFactory>,
+    ) -> anyhow::Result<Self> {
+        let dkim = config.dkim.as_ref().map(DkimSigner::new).transpose()?;
+        let arc = config
+            .arc
+            .as_ref()
+            .map(|arc| ArcSealer::new(arc, config.dkim.as_ref()))
+            .transpose()?;
+
+        Ok(Self {
             config,
             factory,
-            mailer: OnceCell::new(),
-        }
+            mailer: Mutex::new(None),
+            dkim,
+            arc,
+        })
     }
 
-    fn create_envelope(&self, target_address: &str) -> anyhow::Result<Envelope> {
-        let sender_addr = self
-            .config
-            .username
-            .parse()
-            .map_err(|e| anyhow::anyhow!("Invalid sender address: {}", e))?;
-        let target_addr = target_address
-            .parse()
-            .map_err(|e| anyhow::anyhow!("Invalid target address: {}", e))?;
-
-        Envelope::new(Some(sender_addr), vec![target_addr])
+    fn create_envelope(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> anyhow::Result<Envelope> {
+        let sender_addr = self.reverse_path(email)?;
+        let target_addrs = target_addresses
+            .iter()
+            .map(|address| {
+                address
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid target address {}: {}", address, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Envelope::new(sender_addr, target_addrs)
             .map_err(|e| anyhow::anyhow!("Invalid envelope: {}", e))
     }
-}
 
-#[async_trait]
-impl MailSender for SmtpSender {
-    async fn send_email(&self, email: &Email, target_address: &str) -> anyhow::Result<()> {
-        let mailer = self
-            .mailer
-            .get_or_try_init(|| async { self.factory.create(&self.config) })
-            .await?;
+    /// Picks the MAIL FROM address. With SRS enabled, the original sender is
+    /// rewritten into our domain so bounces still find their way back to it, and
+    /// forwarded bounces keep their null reverse path.
+    fn reverse_path(&self, email: &Email) -> anyhow::Result<Option<Address>> {
+        let envelope_from = self.config.envelope_from();
+        let parse_envelope_from = || {
+            envelope_from
+                .parse()
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Invalid sender address {}: {}", envelope_from, e))
+        };
 
-        let envelope = self.create_envelope(target_address)?;
+        let Some(secret) = &self.config.srs_secret else {
+            return parse_envelope_from();
+        };
+        // A wrapped email is our own message, so its bounces come back to us
+        if self.config.forward_mode == Some(ForwardMode::Attach) {
+            return parse_envelope_from();
+        }
+        let domain = self.config.srs_domain().ok_or_else(|| {
+            anyhow::anyhow!("SRS needs srs_domain or an envelope_from address with a domain")
+        })?;
 
-        let mut final_content = Vec::with_capacity(email.content.len() + 32);
+        match srs::bounce_address(&email.content) {
+            Some(original) if original.is_empty() => Ok(None),
+            Some(original) => {
+                let rewritten = Srs::new(secret, domain).forward(&original);
+                match rewritten.parse() {
+                    Ok(address) => Ok(Some(address)),
+                    Err(e) => {
+                        warn!(
+                            "Cannot use SRS address {} for email {}: {}, falling back to {}",
+                            rewritten, email.id, e, envelope_from
+                        );
+                        parse_envelope_from()
+                    }
+                }
+            }
+            None => parse_envelope_from(),
+        }
+    }
 
-        // Add custom headers
-        final_content.extend_from_slice(b"X-Forwarded-By: mail-forwarder\r\n");
+    /// Builds the header lines to prepend to a forwarded email, as selected by `header_mode`.
+    fn trace_headers(&self, email: &Email, target_addresses: &[String]) -> Vec<u8> {
+        let now = chrono::Utc::now();
+        let headers = match self.config.header_mode.unwrap_or_default() {
+            HeaderMode::XHeaders => format!(
+                "X-Forwarded-By: mail-forwarder\r\n\
+                 X-Original-Message-ID: {}\r\n\
+                 X-Forwarded-Time: {}\r\n",
+                email.id,
+                now.to_rfc3339()
+            ),
+            // Resent blocks are prepended, so the most recent hop comes first
+            HeaderMode::Resent => format!(
+                "Resent-Date: {}\r\n\
+                 Resent-From: {}\r\n\
+                 Resent-To: {}\r\n\
+                 Resent-Message-ID: {}\r\n",
+                now.to_rfc2822(),
+                self.config.envelope_from(),
+                target_addresses.join(", "),
+                self.message_id()
+            ),
+            HeaderMode::None => String::new(),
+        };
+        headers.into_bytes()
+    }
+
+    /// Builds a new message from our own address to the targets, with a short
+    /// summary of the original email and the original attached as `message/rfc822`.
+    fn wrap_as_attachment(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> anyhow::Result<Vec<u8>> {
+        let original = MessageParser::default().parse_headers(&email.content);
+        let original = original.as_ref();
+        let raw_header = |name: &str| {
+            original
+                .and_then(|message| message.header_raw(name))
+                .map_or("(unknown)".to_string(), |value| value.trim().to_string())
+        };
+        let subject = original
+            .and_then(|message| message.subject())
+            .unwrap_or_default();
 
-        final_content.extend_from_slice(b"X-Original-Message-ID: ");
-        final_content.extend_from_slice(email.id.as_bytes());
-        final_content.extend_from_slice(b"\r\n");
+        let summary = format!(
+            "The original message is attached.\r\n\
+             \r\n\
+             From: {}\r\n\
+             Date: {}\r\n\
+             Subject: {}\r\n",
+            raw_header("From"),
+            raw_header("Date"),
+            subject
+        );
 
-        final_content.extend_from_slice(b"X-Forwarded-Time: ");
-        final_content.extend_from_slice(chrono::Utc::now().to_rfc3339().as_bytes());
-        final_content.extend_from_slice(b"\r\n");
+        let from: Mailbox = self.config.envelope_from().parse().map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid sender address {}: {}",
+                self.config.envelope_from(),
+                e
+            )
+        })?;
+        let mut builder = Message::builder()
+            .from(from)
+            .subject(forwarded_subject(subject))
+            .message_id(Some(self.message_id()));
+        for address in target_addresses {
+            let to: Mailbox = address
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid target address {}: {}", address, e))?;
+            builder = builder.to(to);
+        }
 
-        final_content.extend_from_slice(&email.content);
+        // Replies should reach the original sender, not the forwarder
+        let original_sender = original
+            .and_then(|message| message.from())
+            .and_then(|from| from.first());
+        if let Some(sender) = original_sender
+            && let Some(Ok(address)) = sender.address().map(str::parse::<Address>)
+        {
+            let name = sender.name().map(str::to_string);
+            builder = builder.reply_to(Mailbox::new(name, address));
+        }
+
+        // message/rfc822 parts may not be base64 encoded (RFC 2046 section 5.2.1)
+        let encoding = if email.content.is_ascii() {
+            ContentTransferEncoding::SevenBit
+        } else {
+            ContentTransferEncoding::EightBit
+        };
+        let attachment = SinglePart::builder()
+            .header(ContentType::parse("message/rfc822")?)
+            .header(ContentDisposition::attachment("forwarded.eml"))
+            .body(Body::dangerous_pre_encoded(email.content.clone(), encoding));
+
+        let message = builder
+            .multipart(
+                MultiPart::mixed()
+                    .singlepart(SinglePart::plain(summary))
+                    .singlepart(attachment),
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to build forwarded message: {}", e))?;
+        Ok(message.formatted())
+    }
+
+    /// Builds the envelope and the exact content that forwarding an email sends:
+    /// the original with trace headers or wrapped as an attachment, then signed
+    /// and sealed as configured. Failures here are permanent.
+    fn prepare(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> Result<(Envelope, Vec<u8>), SendError> {
+        // An invalid address will not become valid by retrying
+        let envelope = self
+            .create_envelope(email, target_addresses)
+            .map_err(|e| SendError::permanent(e.to_string()))?;
+
+        let forward_mode = self.config.forward_mode.unwrap_or_default();
+        let mut final_content = match forward_mode {
+            ForwardMode::Raw => {
+                let mut content = self.trace_headers(email, target_addresses);
+                content.extend_from_slice(&email.content);
+                content
+            }
+            ForwardMode::Attach => self
+                .wrap_as_attachment(email, target_addresses)
+                .map_err(|e| SendError::permanent(e.to_string()))?,
+        };
+
+        if let Some(dkim) = &self.dkim {
+            let signature = dkim.sign(&final_content);
+            final_content.splice(0..0, signature.into_bytes());
+        }
+
+        // A wrapped email is a new message, there is no chain to continue
+        if let Some(arc) = &self.arc
+            && forward_mode == ForwardMode::Raw
+        {
+            match arc.seal(&final_content) {
+                Some(seal) => {
+                    final_content.splice(0..0, seal.into_bytes());
+                }
+                None => warn!(
+                    "Not sealing email {}: its ARC chain is already at the limit",
+                    email.id
+                ),
+            }
+        }
+
+        Ok((envelope, final_content))
+    }
+
+    /// Describes what forwarding an email would send, without connecting to the server.
+    pub fn preview(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> Result<ForwardPreview, SendError> {
+        let (envelope, content) = self.prepare(email, target_addresses)?;
+        let attached = self.config.forward_mode == Some(ForwardMode::Attach);
+
+        // Everything in front of the original is ours, a wrapped email is all new
+        let added = if attached {
+            let end = content
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .unwrap_or(content.len());
+            &content[..end]
+        } else {
+            &content[..content.len() - email.content.len()]
+        };
+
+        Ok(ForwardPreview {
+            envelope_from: envelope.from().map(|address| address.to_string()),
+            added_headers: header_names(added),
+            attached,
+        })
+    }
+
+    /// Generates a unique message ID in the domain of the envelope sender.
+    fn message_id(&self) -> String {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let domain = self
+            .config
+            .envelope_from()
+            .rsplit_once('@')
+            .map_or(self.config.host.as_str(), |(_, domain)| domain);
+        format!(
+            "<{}.{}.{}@{}>",
+            chrono::Utc::now().timestamp_micros(),
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            domain
+        )
+    }
+
+    /// Returns the mailer, creating it again when `password_command` has
+    /// produced a different password since it was created.
+    async fn mailer(&self) -> anyhow::Result<Arc<dyn SmtpMailer>> {
+        let password = password(&self.config).await?;
+
+        let mut mailer = self.mailer.lock().unwrap();
+        if let Some((current, existing)) = mailer.as_ref()
+            && *current == password
+        {
+            return Ok(existing.clone());
+        }
+
+        let config = SenderConfig {
+            password: password.clone(),
+            password_command: None,
+            ..self.config.clone()
+        };
+        let created: Arc<dyn SmtpMailer> = Arc::from(self.factory.create(&config)?);
+        *mailer = Some((password, created.clone()));
+        Ok(created)
+    }
+}
+
+#[async_trait]
+impl MailSender for SmtpSender {
+    async fn send_email(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> Result<(), SendError> {
+        let (envelope, final_content) = self.prepare(email, target_addresses)?;
+
+        let mailer = self
+            .mailer()
+            .await
+            .map_err(|e| SendError::transient(e.to_string()))?;
 
         mailer.send(envelope, &final_content).await
     }
 }
+
+/// Prefixes the subject of a wrapped email with "Fwd:", unless it already has one.
+fn forwarded_subject(subject: &str) -> String {
+    let lowercase = subject.trim_start().to_lowercase();
+    if lowercase.starts_with("fwd:") || lowercase.starts_with("fw:") {
+        subject.to_string()
+    } else {
+        format!("Fwd: {}", subject)
+    }
+}
+
+/// Lists the field names of a block of header lines, skipping folded continuations.
+fn header_names(headers: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(headers)
+        .split("\r\n")
+        .filter(|line| !line.starts_with([' ', '\t']))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
@@ -0,0 +1,598 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: spool.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 18:11:46
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 18:11:46
+ */
+
+use crate::config::{
+    DEFAULT_SPOOL_MAX_ATTEMPTS, DEFAULT_SPOOL_RETRY_INITIAL_SECONDS,
+    DEFAULT_SPOOL_RETRY_MAX_SECONDS, SpoolConfig,
+};
+use crate::traits::{Email, SendError};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Delivery state of a spooled email, stored next to the message as `<name>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolEntry {
+    /// The receiver account the email came from, used for logging.
+    pub receiver: String,
+    /// The email ID on the source server.
+    pub email_id: String,
+    pub target_addresses: Vec<String>,
+    /// Unix timestamp the email was queued at.
+    pub queued_at: i64,
+    pub attempts: u32,
+    /// Unix timestamp of the next delivery attempt.
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+}
+
+/// An email loaded from the spool, ready for a delivery attempt.
+pub struct SpooledEmail {
+    name: String,
+    pub entry: SpoolEntry,
+    pub email: Email,
+}
+
+/// What happened to an email after a failed delivery attempt.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RetryOutcome {
+    /// Another attempt is scheduled after the given delay.
+    Retry(Duration),
+    /// The email ran out of attempts or time and was moved to the dead-letter folder.
+    DeadLettered,
+}
+
+/// A persistent outbound queue.
+///
+/// Every email is written as `<name>.eml` plus a `<name>.json` delivery state,
+/// each to a temp file that is synced and then renamed into place. The state file
+/// is written last, so an email only counts as spooled once both are durable.
+pub struct Spool {
+    dir: PathBuf,
+    dead_dir: PathBuf,
+    max_attempts: u32,
+    max_age: Option<Duration>,
+    retry_initial: Duration,
+    retry_max: Duration,
+    counter: AtomicU64,
+    wake: Notify,
+}
+
+impl Spool {
+    /// Opens (or creates) the spool and its dead-letter folder.
+    pub fn open(config: &SpoolConfig) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        let dead_dir = dir.join("dead");
+        fs::create_dir_all(&dead_dir).map_err(|e| {
+            anyhow::anyhow!("Failed to create spool directory {}: {}", dir.display(), e)
+        })?;
+
+        Ok(Self {
+            dir,
+            dead_dir,
+            max_attempts: config
+                .max_attempts
+                .unwrap_or(DEFAULT_SPOOL_MAX_ATTEMPTS)
+                .max(1),
+            max_age: config
+                .max_age_hours
+                .map(|hours| Duration::from_secs(hours * 60 * 60)),
+            retry_initial: Duration::from_secs(
+                config
+                    .retry_initial_seconds
+                    .unwrap_or(DEFAULT_SPOOL_RETRY_INITIAL_SECONDS),
+            ),
+            retry_max: Duration::from_secs(
+                config
+                    .retry_max_seconds
+                    .unwrap_or(DEFAULT_SPOOL_RETRY_MAX_SECONDS),
+            ),
+            counter: AtomicU64::new(0),
+            wake: Notify::new(),
+        })
+    }
+
+    /// Durably queues an email for delivery. Once this returns `Ok`, the email
+    /// may be removed from the source server.
+    pub fn enqueue(
+        &self,
+        receiver: &str,
+        email: &Email,
+        target_addresses: &[String],
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now();
+        let name = format!(
+            "{}-{}-{:06}",
+            now.timestamp_micros(),
+            std::process::id(),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let entry = SpoolEntry {
+            receiver: receiver.to_string(),
+            email_id: email.id.clone(),
+            target_addresses: target_addresses.to_vec(),
+            queued_at: now.timestamp(),
+            attempts: 0,
+            next_attempt_at: now.timestamp(),
+            last_error: None,
+        };
+
+        write_atomically(&self.dir.join(format!("{}.eml", name)), &email.content)?;
+        write_atomically(
+            &self.dir.join(format!("{}.json", name)),
+            &serde_json::to_vec_pretty(&entry)?,
+        )?;
+
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Waits until a new email is queued.
+    pub async fn notified(&self) {
+        self.wake.notified().await
+    }
+
+    /// Loads all emails whose next attempt is due, in the order they were queued.
+    /// Entries that cannot be loaded are moved to the dead-letter folder, so one
+    /// bad entry does not hold up the rest.
+    pub fn due(&self) -> anyhow::Result<Vec<SpooledEmail>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut due = Vec::new();
+
+        let entries = fs::read_dir(&self.dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to read spool directory {}: {}",
+                self.dir.display(),
+                e
+            )
+        })?;
+        for dir_entry in entries {
+            let path = match dir_entry {
+                Ok(dir_entry) => dir_entry.path(),
+                Err(e) => {
+                    warn!("Failed to read spool directory entry: {}", e);
+                    continue;
+                }
+            };
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            match self.load(name, now) {
+                Ok(Some(spooled)) => due.push(spooled),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("{}, moving it to {}", e, self.dead_dir.display());
+                    if let Err(e) = self.quarantine(name) {
+                        error!("Failed to move spool entry {} aside: {}", name, e);
+                    }
+                }
+            }
+        }
+
+        due.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(due)
+    }
+
+    /// Loads the email stored as `name` if its next attempt is due.
+    fn load(&self, name: &str, now: i64) -> anyhow::Result<Option<SpooledEmail>> {
+        let path = self.dir.join(format!("{}.json", name));
+        let entry: SpoolEntry = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice(&data)?))
+            .map_err(|e| anyhow::anyhow!("Corrupt spool entry {}: {}", path.display(), e))?;
+        if entry.next_attempt_at > now {
+            return Ok(None);
+        }
+
+        let eml = self.dir.join(format!("{}.eml", name));
+        let content = fs::read(&eml).map_err(|e| {
+            anyhow::anyhow!(
+                "Spool entry {} has no readable message {}: {}",
+                path.display(),
+                eml.display(),
+                e
+            )
+        })?;
+        Ok(Some(SpooledEmail {
+            name: name.to_string(),
+            email: Email {
+                id: entry.email_id.clone(),
+                content,
+            },
+            entry,
+        }))
+    }
+
+    /// Moves whatever is left of an entry that cannot be loaded to the
+    /// dead-letter folder, state file first so it is not loaded again. A file
+    /// already in the dead-letter folder, written by an interrupted
+    /// `dead_letter`, is newer and kept.
+    fn quarantine(&self, name: &str) -> anyhow::Result<()> {
+        for file in [format!("{}.json", name), format!("{}.eml", name)] {
+            let path = self.dir.join(&file);
+            if !path.exists() {
+                continue;
+            }
+            let dead = self.dead_dir.join(&file);
+            if dead.exists() {
+                fs::remove_file(&path)?;
+            } else {
+                fs::rename(&path, dead)?;
+            }
+        }
+        sync_dir(&self.dead_dir)?;
+        sync_dir(&self.dir)
+    }
+
+    /// Removes a delivered email from the spool.
+    pub fn complete(&self, spooled: &SpooledEmail) -> anyhow::Result<()> {
+        fs::remove_file(self.dir.join(format!("{}.json", spooled.name)))?;
+        fs::remove_file(self.dir.join(format!("{}.eml", spooled.name)))?;
+        sync_dir(&self.dir)
+    }
+
+    /// Records a failed delivery attempt. Schedules a retry with exponential
+    /// backoff, or moves the email to the dead-letter folder right away on a
+    /// permanent error, or once it has used up its attempts or exceeded the maximum age.
+    pub fn fail(
+        &self,
+        spooled: &mut SpooledEmail,
+        error: &SendError,
+    ) -> anyhow::Result<RetryOutcome> {
+        let now = chrono::Utc::now().timestamp();
+        let entry = &mut spooled.entry;
+        entry.attempts += 1;
+        entry.last_error = Some(error.to_string());
+
+        let expired = self
+            .max_age
+            .is_some_and(|max_age| now - entry.queued_at >= max_age.as_secs() as i64);
+        if !error.transient || entry.attempts >= self.max_attempts || expired {
+            self.dead_letter(spooled)?;
+            return Ok(RetryOutcome::DeadLettered);
+        }
+
+        let delay = self.backoff(entry.attempts);
+        entry.next_attempt_at = now + delay.as_secs() as i64;
+        write_atomically(
+            &self.dir.join(format!("{}.json", spooled.name)),
+            &serde_json::to_vec_pretty(entry)?,
+        )?;
+
+        Ok(RetryOutcome::Retry(delay))
+    }
+
+    /// Moves an email to the dead-letter folder: the message first, then the
+    /// state file, which is removed from the spool last. A crash in between
+    /// leaves a spool entry without its message, which the next scan moves
+    /// aside, completing the dead entry.
+    fn dead_letter(&self, spooled: &SpooledEmail) -> anyhow::Result<()> {
+        let json = format!("{}.json", spooled.name);
+        let eml = format!("{}.eml", spooled.name);
+
+        fs::rename(self.dir.join(&eml), self.dead_dir.join(&eml))?;
+        sync_dir(&self.dead_dir)?;
+        sync_dir(&self.dir)?;
+        write_atomically(
+            &self.dead_dir.join(&json),
+            &serde_json::to_vec_pretty(&spooled.entry)?,
+        )?;
+        fs::remove_file(self.dir.join(&json))?;
+        sync_dir(&self.dir)
+    }
+
+    /// The delay before the given attempt: the initial delay doubled for every
+    /// previous failure, capped at the configured maximum.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.retry_initial
+            .saturating_mul(factor)
+            .min(self.retry_max)
+    }
+
+    pub fn dead_dir(&self) -> &Path {
+        &self.dead_dir
+    }
+}
+
+/// Writes a sibling temp file, syncs it and renames it over `path`, then syncs
+/// the directory so the rename itself survives a crash.
+pub fn write_atomically(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut file = fs::File::create(&tmp_path)
+            .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", tmp_path.display(), e))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&tmp_path, path)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+    sync_parent(path)
+}
+
+/// Syncs the directory holding `path`, after creating, renaming or removing it.
+pub fn sync_parent(path: &Path) -> anyhow::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+/// Syncs a directory, making the files created, renamed or removed in it durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> anyhow::Result<()> {
+    fs::File::open(dir)
+        .and_then(|dir_file| dir_file.sync_all())
+        .map_err(|e| anyhow::anyhow!("Failed to sync directory {}: {}", dir.display(), e))
+}
+
+/// Directories cannot be opened as files here; renames are durable once the
+/// file system flushes its metadata.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_spool(name: &str, config: SpoolConfig) -> (Spool, PathBuf) {
+        let dir = env::temp_dir().join(format!(
+            "mail-forwarder-spool-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let spool = Spool::open(&SpoolConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            ..config
+        })
+        .unwrap();
+        (spool, dir)
+    }
+
+    fn email(id: &str) -> Email {
+        Email {
+            id: id.to_string(),
+            content: format!("Subject: {}\r\n\r\nbody\r\n", id).into_bytes(),
+        }
+    }
+
+    fn targets() -> Vec<String> {
+        vec!["target@example.com".to_string()]
+    }
+
+    #[test]
+    fn test_enqueue_survives_reopen_and_completes() {
+        let (spool, dir) = test_spool("reopen", SpoolConfig::default());
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+        spool.enqueue("user", &email("2"), &targets()).unwrap();
+
+        let reopened = Spool::open(&SpoolConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let due = reopened.due().unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].email.id, "1");
+        assert_eq!(due[0].email.content, email("1").content);
+        assert_eq!(due[0].entry.target_addresses, targets());
+
+        reopened.complete(&due[0]).unwrap();
+        assert_eq!(reopened.due().unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_delivery_is_retried_later() {
+        let (spool, dir) = test_spool(
+            "backoff",
+            SpoolConfig {
+                retry_initial_seconds: Some(60),
+                retry_max_seconds: Some(300),
+                ..Default::default()
+            },
+        );
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+
+        let mut due = spool.due().unwrap();
+        let outcome = spool
+            .fail(&mut due[0], &SendError::transient("451 try again"))
+            .unwrap();
+        assert_eq!(outcome, RetryOutcome::Retry(Duration::from_secs(60)));
+        assert!(spool.due().unwrap().is_empty());
+
+        assert_eq!(spool.backoff(2), Duration::from_secs(120));
+        assert_eq!(spool.backoff(3), Duration::from_secs(240));
+        assert_eq!(spool.backoff(4), Duration::from_secs(300));
+        assert_eq!(spool.backoff(40), Duration::from_secs(300));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let (spool, dir) = test_spool(
+            "dead",
+            SpoolConfig {
+                max_attempts: Some(2),
+                retry_initial_seconds: Some(0),
+                ..Default::default()
+            },
+        );
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+
+        let mut due = spool.due().unwrap();
+        assert_eq!(
+            spool
+                .fail(&mut due[0], &SendError::transient("451 try again"))
+                .unwrap(),
+            RetryOutcome::Retry(Duration::ZERO)
+        );
+
+        let mut due = spool.due().unwrap();
+        assert_eq!(due[0].entry.attempts, 1);
+        assert_eq!(
+            spool
+                .fail(&mut due[0], &SendError::transient("451 still failing"))
+                .unwrap(),
+            RetryOutcome::DeadLettered
+        );
+        assert!(spool.due().unwrap().is_empty());
+
+        let dead: Vec<_> = fs::read_dir(spool.dead_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(dead.len(), 2);
+        let state = dead
+            .iter()
+            .find(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .unwrap();
+        let entry: SpoolEntry = serde_json::from_slice(&fs::read(state).unwrap()).unwrap();
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(entry.last_error.as_deref(), Some("451 still failing"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_permanent_error_is_not_retried() {
+        let (spool, dir) = test_spool("permanent", SpoolConfig::default());
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+
+        let mut due = spool.due().unwrap();
+        let error = SendError::from_reply(550, "5.1.1 User unknown", "SMTP send failed");
+        assert_eq!(
+            spool.fail(&mut due[0], &error).unwrap(),
+            RetryOutcome::DeadLettered
+        );
+        assert_eq!(due[0].entry.attempts, 1);
+        assert!(spool.due().unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bad_entries_are_moved_aside() {
+        let (spool, dir) = test_spool("bad", SpoolConfig::default());
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+        spool.enqueue("user", &email("2"), &targets()).unwrap();
+        spool.enqueue("user", &email("3"), &targets()).unwrap();
+
+        let names: Vec<String> = spool.due().unwrap().into_iter().map(|s| s.name).collect();
+        // A state file without its message, as left by a crash, and a corrupt one
+        fs::remove_file(dir.join(format!("{}.eml", names[0]))).unwrap();
+        fs::write(dir.join(format!("{}.json", names[1])), b"{ not json").unwrap();
+
+        let due = spool.due().unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].email.id, "3");
+
+        let mut dead: Vec<String> = fs::read_dir(spool.dead_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        dead.sort();
+        assert_eq!(
+            dead,
+            [
+                format!("{}.json", names[0]),
+                format!("{}.eml", names[1]),
+                format!("{}.json", names[1]),
+            ]
+        );
+
+        // Nothing is left behind to fail the next scan
+        assert_eq!(spool.due().unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_interrupted_dead_letter_is_completed() {
+        let (spool, dir) = test_spool("interrupted", SpoolConfig::default());
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+        spool.enqueue("user", &email("2"), &targets()).unwrap();
+
+        let mut due = spool.due().unwrap();
+        let names: Vec<String> = due.iter().map(|s| s.name.clone()).collect();
+        // A crash right after the message was moved, and one right after the
+        // dead state file was written, before the spool one was removed
+        for (spooled, write_state) in due.iter_mut().zip([false, true]) {
+            let eml = format!("{}.eml", spooled.name);
+            fs::rename(dir.join(&eml), spool.dead_dir().join(&eml)).unwrap();
+            if write_state {
+                spooled.entry.attempts = 5;
+                fs::write(
+                    spool.dead_dir().join(format!("{}.json", spooled.name)),
+                    serde_json::to_vec_pretty(&spooled.entry).unwrap(),
+                )
+                .unwrap();
+            }
+        }
+
+        assert!(spool.due().unwrap().is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        for (name, attempts) in names.iter().zip([0, 5]) {
+            assert!(spool.dead_dir().join(format!("{}.eml", name)).exists());
+            let state = fs::read(spool.dead_dir().join(format!("{}.json", name))).unwrap();
+            let entry: SpoolEntry = serde_json::from_slice(&state).unwrap();
+            assert_eq!(entry.attempts, attempts);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_age() {
+        let (spool, dir) = test_spool(
+            "expired",
+            SpoolConfig {
+                max_age_hours: Some(1),
+                ..Default::default()
+            },
+        );
+        spool.enqueue("user", &email("1"), &targets()).unwrap();
+
+        let mut due = spool.due().unwrap();
+        due[0].entry.queued_at -= 2 * 60 * 60;
+        assert_eq!(
+            spool
+                .fail(&mut due[0], &SendError::transient("451 try again"))
+                .unwrap(),
+            RetryOutcome::DeadLettered
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
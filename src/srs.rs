@@ -0,0 +1,373 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: srs.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 18:40:26
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 18:40:26
+ */
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use mail_parser::MessageParser;
+use sha1::Sha1;
+
+/// Characters of the SRS timestamp, which counts days modulo 1024 in two base32 digits.
+const TIMESTAMP_CHARS: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Length of the truncated, base64-encoded HMAC in a rewritten address.
+const HASH_LENGTH: usize = 4;
+const SECONDS_PER_DAY: u64 = 86400;
+/// Days an SRS0 address stays valid for bounces, as in other SRS implementations.
+const MAX_AGE_DAYS: u64 = 21;
+
+/// Rewrites envelope senders with the Sender Rewriting Scheme, so forwarded mail
+/// passes SPF at the destination while bounces can still reach the original sender.
+///
+/// `user@example.org` becomes `SRS0=HHHH=TT=example.org=user@<domain>`, and an
+/// address another forwarder already rewrote becomes an `SRS1` address that keeps
+/// the first forwarder's domain, so its length does not grow with every hop.
+///
+/// Bounces to these addresses go to the mail server of the SRS domain, not to us;
+/// it has to turn them back into the original address with the same secret, which
+/// [`Srs::reverse`] does, e.g. for the `srs-reverse` command.
+pub struct Srs {
+    secret: String,
+    domain: String,
+}
+
+impl Srs {
+    pub fn new(secret: &str, domain: &str) -> Self {
+        Self {
+            secret: secret.to_string(),
+            domain: domain.to_string(),
+        }
+    }
+
+    /// Rewrites `sender` into an address in our domain. Addresses already in our
+    /// domain, and those without a domain, are returned unchanged.
+    pub fn forward(&self, sender: &str) -> String {
+        self.forward_at(sender, days_now())
+    }
+
+    fn forward_at(&self, sender: &str, days: u64) -> String {
+        let Some((local, domain)) = sender.rsplit_once('@') else {
+            return sender.to_string();
+        };
+        if domain.eq_ignore_ascii_case(&self.domain) {
+            return sender.to_string();
+        }
+
+        // SRS0=HHHH=TT=orig-domain=orig-local@hop becomes SRS1=HHHH=hop==HHHH=TT=...
+        if let Some(rest) = strip_tag(local, "SRS0") {
+            let rest = format!("={}", rest);
+            return format!(
+                "SRS1={}={}={}@{}",
+                self.hash(&[domain, &rest]),
+                domain,
+                rest,
+                self.domain
+            );
+        }
+
+        // SRS1=HHHH=first-hop==... keeps the first hop and only gets a new hash
+        if let Some(rest) = strip_tag(local, "SRS1")
+            && let Some((_, rest)) = rest.split_once('=')
+            && let Some((first_hop, rest)) = rest.split_once('=')
+        {
+            return format!(
+                "SRS1={}={}={}@{}",
+                self.hash(&[first_hop, rest]),
+                first_hop,
+                rest,
+                self.domain
+            );
+        }
+
+        let timestamp = timestamp(days);
+        format!(
+            "SRS0={}={}={}={}@{}",
+            self.hash(&[&timestamp, domain, local]),
+            timestamp,
+            domain,
+            local,
+            self.domain
+        )
+    }
+
+    /// Turns an address [`Srs::forward`] created back into the one it replaced:
+    /// the original sender for SRS0, the previous forwarder's SRS0 address for
+    /// SRS1. Fails for other addresses, a wrong hash and expired SRS0 addresses.
+    pub fn reverse(&self, address: &str) -> anyhow::Result<String> {
+        self.reverse_at(address, days_now())
+    }
+
+    fn reverse_at(&self, address: &str, days: u64) -> anyhow::Result<String> {
+        let (local, _) = address
+            .rsplit_once('@')
+            .filter(|(_, domain)| domain.eq_ignore_ascii_case(&self.domain))
+            .ok_or_else(|| anyhow::anyhow!("{} is not an address in {}", address, self.domain))?;
+
+        if let Some(rest) = strip_tag(local, "SRS0") {
+            let mut parts = rest.splitn(4, '=');
+            let (Some(hash), Some(timestamp), Some(orig_domain), Some(orig_local)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                anyhow::bail!("{} is not a valid SRS0 address", address);
+            };
+            self.check_hash(hash, &[timestamp, orig_domain, orig_local], address)?;
+            let age = timestamp_age(timestamp, days)
+                .ok_or_else(|| anyhow::anyhow!("{} has an invalid timestamp", address))?;
+            if age > MAX_AGE_DAYS {
+                anyhow::bail!("{} expired {} days ago", address, age - MAX_AGE_DAYS);
+            }
+            return Ok(format!("{}@{}", orig_local, orig_domain));
+        }
+
+        if let Some(rest) = strip_tag(local, "SRS1") {
+            let Some((hash, rest)) = rest.split_once('=') else {
+                anyhow::bail!("{} is not a valid SRS1 address", address);
+            };
+            let Some((first_hop, rest)) = rest.split_once('=') else {
+                anyhow::bail!("{} is not a valid SRS1 address", address);
+            };
+            self.check_hash(hash, &[first_hop, rest], address)?;
+            return Ok(format!("SRS0{}@{}", rest, first_hop));
+        }
+
+        anyhow::bail!("{} is not an SRS address", address)
+    }
+
+    /// Compares hashes case-insensitively, as some mail servers lowercase addresses.
+    fn check_hash(&self, hash: &str, parts: &[&str], address: &str) -> anyhow::Result<()> {
+        if hash.eq_ignore_ascii_case(&self.hash(parts)) {
+            Ok(())
+        } else {
+            anyhow::bail!("{} has an invalid hash", address)
+        }
+    }
+
+    /// HMAC-SHA1 over the lowercased parts, base64 encoded and truncated.
+    fn hash(&self, parts: &[&str]) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part.to_lowercase().as_bytes());
+        }
+        let mut hash = BASE64.encode(mac.finalize().into_bytes());
+        hash.truncate(HASH_LENGTH);
+        hash
+    }
+}
+
+/// Strips a case-insensitive `SRS0`/`SRS1` tag and the separator that follows it.
+fn strip_tag<'a>(local: &'a str, tag: &str) -> Option<&'a str> {
+    let prefix = local.get(..tag.len() + 1)?;
+    let (name, separator) = prefix.split_at(tag.len());
+    if name.eq_ignore_ascii_case(tag) && matches!(separator, "=" | "+" | "-") {
+        Some(&local[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn days_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY
+}
+
+fn timestamp(days: u64) -> String {
+    let days = (days % 1024) as usize;
+    [TIMESTAMP_CHARS[days >> 5], TIMESTAMP_CHARS[days & 31]]
+        .iter()
+        .map(|&c| c as char)
+        .collect()
+}
+
+/// Days since `timestamp` was created on day `days`, modulo 1024.
+fn timestamp_age(timestamp: &str, days: u64) -> Option<u64> {
+    let [high, low] = timestamp.as_bytes() else {
+        return None;
+    };
+    let digit = |c: &u8| {
+        TIMESTAMP_CHARS
+            .iter()
+            .position(|t| t.eq_ignore_ascii_case(c))
+            .map(|d| d as u64)
+    };
+    let then = (digit(high)? << 5) | digit(low)?;
+    Some((days % 1024 + 1024 - then) % 1024)
+}
+
+/// Finds the address bounces of a raw message should go to: the Return-Path the
+/// receiving server recorded, falling back to the first From address. An empty
+/// string means the null reverse path `<>`, i.e. the message is itself a bounce.
+pub fn bounce_address(raw: &[u8]) -> Option<String> {
+    let message = MessageParser::default().parse_headers(raw)?;
+
+    if let Some(return_path) = message.header_raw("Return-Path") {
+        let address = return_path
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        return Some(address.trim().to_string());
+    }
+
+    message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|addr| addr.address())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn srs() -> Srs {
+        Srs::new("s3cr3t", "forwarder.example.com")
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "AA");
+        assert_eq!(timestamp(33), "BB");
+        assert_eq!(timestamp(1023), "77");
+        assert_eq!(timestamp(1024), "AA");
+        assert_eq!(timestamp(20000), "RA");
+    }
+
+    #[test]
+    fn test_srs0() {
+        let rewritten = srs().forward_at("Alice@example.org", 20000);
+        let hash = srs().hash(&["RA", "example.org", "Alice"]);
+        assert_eq!(
+            rewritten,
+            format!("SRS0={}=RA=example.org=Alice@forwarder.example.com", hash)
+        );
+        assert_eq!(hash.len(), HASH_LENGTH);
+
+        // The hash ignores case but depends on the secret and the day
+        assert_eq!(hash, srs().hash(&["ra", "EXAMPLE.ORG", "alice"]));
+        assert_ne!(
+            rewritten,
+            Srs::new("other", "forwarder.example.com").forward_at("Alice@example.org", 20000)
+        );
+        assert_ne!(rewritten, srs().forward_at("Alice@example.org", 20001));
+    }
+
+    #[test]
+    fn test_srs1_from_srs0() {
+        let rewritten = srs().forward_at("SRS0=abcd=AQ=example.org=alice@hop1.example.net", 20000);
+        let hash = srs().hash(&["hop1.example.net", "=abcd=AQ=example.org=alice"]);
+        assert_eq!(
+            rewritten,
+            format!(
+                "SRS1={}=hop1.example.net==abcd=AQ=example.org=alice@forwarder.example.com",
+                hash
+            )
+        );
+    }
+
+    #[test]
+    fn test_srs1_keeps_first_hop() {
+        let rewritten = srs().forward_at(
+            "SRS1=wxyz=hop1.example.net==abcd=AQ=example.org=alice@hop2.example.net",
+            20000,
+        );
+        let hash = srs().hash(&["hop1.example.net", "=abcd=AQ=example.org=alice"]);
+        assert_eq!(
+            rewritten,
+            format!(
+                "SRS1={}=hop1.example.net==abcd=AQ=example.org=alice@forwarder.example.com",
+                hash
+            )
+        );
+    }
+
+    #[test]
+    fn test_own_domain_is_not_rewritten() {
+        assert_eq!(
+            srs().forward_at("me@Forwarder.example.com", 20000),
+            "me@Forwarder.example.com"
+        );
+        assert_eq!(srs().forward_at("postmaster", 20000), "postmaster");
+    }
+
+    #[test]
+    fn test_reverse_round_trip() {
+        let srs0 = srs().forward_at("Alice@example.org", 20000);
+        assert_eq!(srs().reverse_at(&srs0, 20000).unwrap(), "Alice@example.org");
+        // Mail servers may change the case of the local part
+        assert_eq!(
+            srs().reverse_at(&srs0.to_lowercase(), 20010).unwrap(),
+            "alice@example.org"
+        );
+
+        let srs1 = srs().forward_at("SRS0=abcd=AQ=example.org=alice@hop1.example.net", 20000);
+        assert_eq!(
+            srs().reverse_at(&srs1, 30000).unwrap(),
+            "SRS0=abcd=AQ=example.org=alice@hop1.example.net"
+        );
+
+        // Timestamps wrap around after 1024 days
+        let srs0 = srs().forward_at("alice@example.org", 1023);
+        assert_eq!(srs().reverse_at(&srs0, 1030).unwrap(), "alice@example.org");
+    }
+
+    #[test]
+    fn test_reverse_rejects_forged_and_expired_addresses() {
+        let srs0 = srs().forward_at("alice@example.org", 20000);
+
+        let error = srs()
+            .reverse_at(&srs0, 20000 + MAX_AGE_DAYS + 1)
+            .unwrap_err();
+        assert!(error.to_string().ends_with("expired 1 days ago"));
+
+        let forged = srs0.replace("=alice@", "=mallory@");
+        let error = srs().reverse_at(&forged, 20000).unwrap_err();
+        assert!(error.to_string().ends_with("has an invalid hash"));
+        let other_secret = Srs::new("other", "forwarder.example.com");
+        assert!(other_secret.reverse_at(&srs0, 20000).is_err());
+
+        let error = srs().reverse_at("alice@example.org", 20000).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "alice@example.org is not an address in forwarder.example.com"
+        );
+        assert!(srs().reverse_at("me@forwarder.example.com", 20000).is_err());
+        assert!(
+            srs()
+                .reverse_at("SRS0=abcd=AQ@forwarder.example.com", 20000)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_bounce_address() {
+        let with_return_path = b"Return-Path: <bounce+123@lists.example.org>\r\n\
+From: News <news@example.org>\r\n\
+Subject: Hi\r\n\r\nBody\r\n";
+        assert_eq!(
+            bounce_address(with_return_path).as_deref(),
+            Some("bounce+123@lists.example.org")
+        );
+
+        let null_path = b"Return-Path: <>\r\nFrom: MAILER-DAEMON@example.org\r\n\r\nBody\r\n";
+        assert_eq!(bounce_address(null_path).as_deref(), Some(""));
+
+        let from_only = b"From: Alice <alice@example.org>\r\n\r\nBody\r\n";
+        assert_eq!(
+            bounce_address(from_only).as_deref(),
+            Some("alice@example.org")
+        );
+    }
+}
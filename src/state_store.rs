@@ -0,0 +1,522 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: state_store.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 10:12:08
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 10:12:08
+ */
+
+use crate::config::ReceiverConfig;
+use crate::spool;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// On-disk representation of a receiver's state file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    /// The receiver key this file belongs to, kept for human inspection.
+    receiver: String,
+    /// Forwarded email IDs, mapped to the unix timestamp they were recorded at.
+    #[serde(default)]
+    seen_ids: HashMap<String, i64>,
+}
+
+/// A line of the journal: an email ID recorded since the state file was last written.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    id: String,
+    at: i64,
+}
+
+/// Tracks the IDs of emails that have already been forwarded for a single receiver.
+///
+/// When backed by a file, every recorded ID is appended to a journal next to the
+/// state file and synced, so a restart picks up exactly where the last successful
+/// forward left off without rewriting the whole state for every email. Removals,
+/// and opening the store, compact the journal into the state file, which is
+/// written to a temporary file and renamed over the previous state, so a crash
+/// never leaves a half-written file behind.
+pub struct StateStore {
+    key: String,
+    path: Option<PathBuf>,
+    /// The journal, opened by the first insert after a compaction.
+    journal: Option<File>,
+    seen_ids: HashMap<String, i64>,
+}
+
+impl StateStore {
+    /// Creates a store that only lives in memory, used when no `state_dir` is configured.
+    pub fn in_memory(config: &ReceiverConfig) -> Self {
+        Self {
+            key: Self::key(config),
+            path: None,
+            journal: None,
+            seen_ids: HashMap::new(),
+        }
+    }
+
+    /// Opens (or creates) the state file for the given receiver under `state_dir`.
+    pub fn open(state_dir: &str, config: &ReceiverConfig) -> anyhow::Result<Self> {
+        let key = Self::key(config);
+        let dir = Path::new(state_dir);
+        fs::create_dir_all(dir).map_err(|e| {
+            anyhow::anyhow!("Failed to create state directory {}: {}", dir.display(), e)
+        })?;
+
+        let path = dir.join(format!("{}.json", Self::file_name(&key)));
+        let (state, journaled) = Self::read(&path)?;
+
+        let mut store = Self {
+            key,
+            path: Some(path),
+            journal: None,
+            seen_ids: state.seen_ids,
+        };
+        if journaled {
+            store.compact()?;
+        }
+        Ok(store)
+    }
+
+    /// Loads the state file for the given receiver, if there is one, without ever
+    /// writing to it: changes only live in memory. Used by dry runs.
+    pub fn open_read_only(state_dir: &str, config: &ReceiverConfig) -> anyhow::Result<Self> {
+        let key = Self::key(config);
+        let path = Path::new(state_dir).join(format!("{}.json", Self::file_name(&key)));
+        let (state, _) = Self::read(&path)?;
+
+        Ok(Self {
+            key,
+            path: None,
+            journal: None,
+            seen_ids: state.seen_ids,
+        })
+    }
+
+    /// Reads a state file and replays its journal, a missing file being an empty
+    /// state. Also returns whether there was a journal to compact.
+    fn read(path: &Path) -> anyhow::Result<(StateFile, bool)> {
+        let mut state = if path.exists() {
+            let data = fs::read(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read state file {}: {}", path.display(), e)
+            })?;
+            serde_json::from_slice(&data)
+                .map_err(|e| anyhow::anyhow!("Corrupt state file {}: {}", path.display(), e))?
+        } else {
+            StateFile::default()
+        };
+
+        let journal_path = Self::journal_path(path);
+        if !journal_path.exists() {
+            return Ok((state, false));
+        }
+
+        let data = fs::read(&journal_path).map_err(|e| {
+            anyhow::anyhow!("Failed to read journal {}: {}", journal_path.display(), e)
+        })?;
+        // A last line without a newline was cut short by a crash, before its
+        // email was acknowledged anywhere
+        let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        for line in data[..complete].split(|&b| b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let entry: JournalEntry = serde_json::from_slice(line).map_err(|e| {
+                anyhow::anyhow!("Corrupt journal {}: {}", journal_path.display(), e)
+            })?;
+            state.seen_ids.insert(entry.id, entry.at);
+        }
+        Ok((state, true))
+    }
+
+    /// The journal of a state file: the same name, ending in `.journal`.
+    fn journal_path(path: &Path) -> PathBuf {
+        path.with_extension("journal")
+    }
+
+    /// Builds the key identifying a receiver: host, username and folder.
+    pub fn key(config: &ReceiverConfig) -> String {
+        format!("{}/{}/{}", config.host, config.username, config.imap_folder)
+    }
+
+    /// Turns a receiver key into a safe file name, ending in a short hash of the
+    /// key so keys that only differ in replaced characters do not share a file.
+    fn file_name(key: &str) -> String {
+        let hash: String = Sha256::digest(key.as_bytes())[..4]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("{}-{}", Self::file_stem(key), hash)
+    }
+
+    /// Replaces the characters of a receiver key that are unsafe in file names.
+    fn file_stem(key: &str) -> String {
+        key.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '@') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the path of the backing state file, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.seen_ids.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen_ids.len()
+    }
+
+    /// Returns a snapshot of all seen IDs, suitable for `MailReceiver::fetch_emails`.
+    pub fn ids(&self) -> HashSet<String> {
+        self.seen_ids.keys().cloned().collect()
+    }
+
+    /// Records an email ID as forwarded and appends it to the journal.
+    pub fn insert(&mut self, id: &str) -> anyhow::Result<()> {
+        let at = chrono::Utc::now().timestamp();
+        self.seen_ids.insert(id.to_string(), at);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let journal_path = Self::journal_path(path);
+        if self.journal.is_none() {
+            let created = !journal_path.exists();
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&journal_path)
+                .map_err(|e| {
+                    anyhow::anyhow!("Failed to open journal {}: {}", journal_path.display(), e)
+                })?;
+            if created {
+                spool::sync_parent(&journal_path)?;
+            }
+            self.journal = Some(file);
+        }
+
+        let mut line = serde_json::to_vec(&JournalEntry {
+            id: id.to_string(),
+            at,
+        })?;
+        line.push(b'\n');
+        let journal = self.journal.as_mut().expect("journal opened above");
+        journal
+            .write_all(&line)
+            .and_then(|_| journal.sync_data())
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to write journal {}: {}", journal_path.display(), e)
+            })
+    }
+
+    /// Forgets the given email IDs and persists the change.
+    pub fn remove_all(&mut self, ids: &[String]) -> anyhow::Result<()> {
+        let before = self.seen_ids.len();
+        for id in ids {
+            self.seen_ids.remove(id);
+        }
+
+        if self.seen_ids.len() == before {
+            return Ok(());
+        }
+        self.compact()
+    }
+
+    /// Drops IDs the server no longer reports, e.g. emails that were deleted or,
+    /// for IMAP, are no longer unseen. Returns the number of IDs removed.
+    pub fn retain_present(&mut self, server_ids: &HashSet<String>) -> anyhow::Result<usize> {
+        let before = self.seen_ids.len();
+        self.seen_ids.retain(|id, _| server_ids.contains(id));
+
+        let removed = before - self.seen_ids.len();
+        if removed > 0 {
+            self.compact()?;
+        }
+        Ok(removed)
+    }
+
+    /// Enforces the optional age and count limits, evicting the oldest IDs first.
+    /// Only used for receivers that cannot list the IDs on the server, whose
+    /// store `retain_present` cannot prune: an evicted ID whose email is still on
+    /// the server is forwarded again. Returns the number of IDs removed.
+    pub fn enforce_limits(
+        &mut self,
+        max_count: Option<usize>,
+        max_age: Option<Duration>,
+    ) -> anyhow::Result<usize> {
+        let before = self.seen_ids.len();
+
+        if let Some(max_age) = max_age {
+            let cutoff = chrono::Utc::now().timestamp() - max_age.as_secs() as i64;
+            self.seen_ids
+                .retain(|_, recorded_at| *recorded_at >= cutoff);
+        }
+
+        if let Some(max_count) = max_count
+            && self.seen_ids.len() > max_count
+        {
+            let mut oldest_first: Vec<(String, i64)> = self
+                .seen_ids
+                .iter()
+                .map(|(id, recorded_at)| (id.clone(), *recorded_at))
+                .collect();
+            oldest_first.sort_by_key(|(_, recorded_at)| *recorded_at);
+
+            let excess = self.seen_ids.len() - max_count;
+            for (id, _) in oldest_first.into_iter().take(excess) {
+                self.seen_ids.remove(&id);
+            }
+        }
+
+        let removed = before - self.seen_ids.len();
+        if removed > 0 {
+            self.compact()?;
+        }
+        Ok(removed)
+    }
+
+    /// Atomically writes the current state to disk, then drops the journal it
+    /// now includes. A crash in between only replays IDs the state already has,
+    /// or ones just removed, which are pruned again on the next check.
+    fn compact(&mut self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let state = StateFile {
+            receiver: self.key.clone(),
+            seen_ids: self.seen_ids.clone(),
+        };
+        let data = serde_json::to_vec_pretty(&state)?;
+        spool::write_atomically(path, &data)?;
+
+        self.journal = None;
+        let journal_path = Self::journal_path(path);
+        match fs::remove_file(&journal_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to remove journal {}: {}",
+                    journal_path.display(),
+                    e
+                ));
+            }
+        }
+        spool::sync_parent(&journal_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn get_test_config(username: &str) -> ReceiverConfig {
+        ReceiverConfig {
+            host: "pop.example.com".to_string(),
+            port: 995,
+            username: username.to_string(),
+            password: "test_pass".to_string(),
+            protocol: "pop3".to_string(),
+            use_tls: Some(true),
+            check_interval_seconds: Some(60),
+            delete_after_forward: Some(false),
+            imap_folder: "INBOX".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn test_state_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mail-forwarder-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_state_survives_reopen() {
+        let dir = test_state_dir("reopen");
+        let dir_str = dir.to_str().unwrap();
+        let config = get_test_config("user@example.com");
+
+        let mut store = StateStore::open(dir_str, &config).unwrap();
+        assert_eq!(store.len(), 0);
+        store.insert("uid1").unwrap();
+        store.insert("uid2").unwrap();
+        store.remove_all(&["uid1".to_string()]).unwrap();
+
+        let reopened = StateStore::open(dir_str, &config).unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert!(reopened.contains("uid2"));
+        assert!(!reopened.contains("uid1"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_journal_is_replayed_and_compacted() {
+        let dir = test_state_dir("journal");
+        let dir_str = dir.to_str().unwrap();
+        let config = get_test_config("user@example.com");
+
+        let mut store = StateStore::open(dir_str, &config).unwrap();
+        store.insert("uid1").unwrap();
+        store.insert("uid2").unwrap();
+        let state_path = store.path().unwrap().to_path_buf();
+        let journal_path = StateStore::journal_path(&state_path);
+        assert!(!state_path.exists());
+
+        // A crash in the middle of an append leaves a partial last line
+        let mut journal = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        journal.write_all(b"{\"id\":\"uid3\"").unwrap();
+        drop(store);
+
+        let reopened = StateStore::open(dir_str, &config).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert!(reopened.contains("uid1") && reopened.contains("uid2"));
+        assert!(state_path.exists());
+        assert!(!journal_path.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_only_state_is_never_written() {
+        let dir = test_state_dir("read-only");
+        let dir_str = dir.to_str().unwrap();
+        let config = get_test_config("user@example.com");
+
+        // A missing state directory is not created
+        let empty = StateStore::open_read_only(dir_str, &config).unwrap();
+        assert_eq!(empty.len(), 0);
+        assert!(!dir.exists());
+
+        let mut store = StateStore::open(dir_str, &config).unwrap();
+        store.insert("uid1").unwrap();
+
+        let mut read_only = StateStore::open_read_only(dir_str, &config).unwrap();
+        assert!(read_only.contains("uid1"));
+        read_only.insert("uid2").unwrap();
+        read_only.remove_all(&["uid1".to_string()]).unwrap();
+
+        let reopened = StateStore::open(dir_str, &config).unwrap();
+        assert!(reopened.contains("uid1"));
+        assert!(!reopened.contains("uid2"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_receivers_do_not_share_state() {
+        let dir = test_state_dir("isolation");
+        let dir_str = dir.to_str().unwrap();
+
+        let mut first = StateStore::open(dir_str, &get_test_config("a@example.com")).unwrap();
+        first.insert("uid1").unwrap();
+
+        let second = StateStore::open(dir_str, &get_test_config("b@example.com")).unwrap();
+        assert!(!second.contains("uid1"));
+        assert_ne!(first.path(), second.path());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_similar_keys_do_not_share_state() {
+        let dir = test_state_dir("similar-keys");
+        let dir_str = dir.to_str().unwrap();
+
+        // Both keys turn into the same file stem
+        let mut first = StateStore::open(dir_str, &get_test_config("a+b@example.com")).unwrap();
+        first.insert("uid1").unwrap();
+
+        let second = StateStore::open(dir_str, &get_test_config("a_b@example.com")).unwrap();
+        assert!(!second.contains("uid1"));
+        assert_ne!(first.path(), second.path());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_corrupt_state_file_is_an_error() {
+        let dir = test_state_dir("corrupt");
+        let dir_str = dir.to_str().unwrap();
+        let config = get_test_config("user@example.com");
+
+        let store = StateStore::open(dir_str, &config).unwrap();
+        fs::write(store.path().unwrap(), b"not json").unwrap();
+
+        assert!(StateStore::open(dir_str, &config).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_retain_present() {
+        let mut store = StateStore::in_memory(&get_test_config("user@example.com"));
+        store.insert("uid1").unwrap();
+        store.insert("uid2").unwrap();
+        store.insert("uid3").unwrap();
+
+        let server_ids = HashSet::from(["uid2".to_string(), "uid4".to_string()]);
+        assert_eq!(store.retain_present(&server_ids).unwrap(), 2);
+        assert_eq!(store.len(), 1);
+        assert!(store.contains("uid2"));
+    }
+
+    #[test]
+    fn test_enforce_limits() {
+        let now = chrono::Utc::now().timestamp();
+        let mut store = StateStore::in_memory(&get_test_config("user@example.com"));
+        store.seen_ids = HashMap::from([
+            ("ancient".to_string(), now - 10 * 86400),
+            ("old".to_string(), now - 300),
+            ("older".to_string(), now - 600),
+            ("new".to_string(), now),
+        ]);
+
+        // Age limit drops everything older than a day
+        let removed = store
+            .enforce_limits(None, Some(Duration::from_secs(86400)))
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert!(!store.contains("ancient"));
+
+        // Count limit keeps the most recent entries
+        let removed = store.enforce_limits(Some(2), None).unwrap();
+        assert_eq!(removed, 1);
+        assert!(store.contains("new"));
+        assert!(store.contains("old"));
+        assert!(!store.contains("older"));
+
+        assert_eq!(store.enforce_limits(Some(2), None).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_in_memory_store() {
+        let mut store = StateStore::in_memory(&get_test_config("user@example.com"));
+        store.insert("uid1").unwrap();
+        assert!(store.path().is_none());
+        assert!(store.ids().contains("uid1"));
+    }
+}
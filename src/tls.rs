@@ -0,0 +1,67 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: tls.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-17 10:05:41
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-17 10:05:41
+ */
+
+use lettre::transport::smtp::client::{Certificate, CertificateStore, TlsParameters};
+use log::warn;
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig, RootCertStore};
+use std::sync::{Arc, LazyLock};
+
+/// The certificates of the system trust store, the one native-tls uses for
+/// IMAP and proxied POP3, so a private CA installed there is trusted by every
+/// connection, the sender's included. Loaded once per process.
+static SYSTEM_CERTIFICATES: LazyLock<Vec<CertificateDer<'static>>> = LazyLock::new(|| {
+    let result = rustls_native_certs::load_native_certs();
+    for error in &result.errors {
+        warn!("Failed to load system certificates: {}", error);
+    }
+    // Certificates rustls cannot use would fail every STARTTLS setup
+    result
+        .certs
+        .into_iter()
+        .filter(|certificate| RootCertStore::empty().add(certificate.clone()).is_ok())
+        .collect()
+});
+
+static CLIENT_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(root_store())
+            .with_no_client_auth(),
+    )
+});
+
+/// The system trust store, for rustls clients that build their own settings.
+pub fn root_store() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(SYSTEM_CERTIFICATES.iter().cloned());
+    roots
+}
+
+/// The rustls client settings trusting the system trust store.
+pub fn client_config() -> Arc<ClientConfig> {
+    CLIENT_CONFIG.clone()
+}
+
+/// The STARTTLS settings for `host`, trusting the system trust store. lettre
+/// builds its rustls settings from these, so they are meant to be built once
+/// per server and reused for every session.
+pub fn starttls_parameters(host: &str) -> Result<TlsParameters, lettre::transport::smtp::Error> {
+    let mut builder =
+        TlsParameters::builder(host.to_string()).certificate_store(CertificateStore::None);
+    for certificate in SYSTEM_CERTIFICATES.iter() {
+        builder = builder.add_root_certificate(Certificate::from_der(certificate.to_vec())?);
+    }
+    builder.build_rustls()
+}
@@ -14,6 +14,8 @@
 
 use async_trait::async_trait;
 use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct Email {
@@ -21,14 +23,39 @@ pub struct Email {
     pub content: Vec<u8>,
 }
 
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait MailReceiver: Send + Sync {
     /// Connects/Authenticates and fetches pending emails, skipping those in `seen_ids`
     async fn fetch_emails(&mut self, seen_ids: &HashSet<String>) -> anyhow::Result<Vec<Email>>;
 
+    /// Optional: All IDs the server reported during the last `fetch_emails`, seen or not.
+    /// Used to prune tracked IDs of emails that no longer exist on the server.
+    // The lifetime is named for mockall, which cannot mock it elided
+    #[allow(clippy::needless_lifetimes)]
+    fn server_ids<'a>(&'a self) -> Option<&'a HashSet<String>> {
+        None
+    }
+
+    /// Optional: Block until the server announces new mail or `timeout` elapses.
+    /// Returns `Ok(false)` if push notifications are not available, in which case
+    /// the caller falls back to polling at the regular interval.
+    async fn wait_for_new_mail(&mut self, _timeout: Duration) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    /// Optional: Flag emails as seen on the server. A no-op for protocols without flags.
+    async fn mark_seen(&mut self, _ids: &[String]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Optional: Delete an email after processing
     async fn delete_email(&mut self, id: &str) -> anyhow::Result<()>;
 
+    /// Connects, logs in and counts the messages in the mailbox without fetching
+    /// or changing anything. Failures carry a `ConnectError` naming the failed step.
+    async fn check_connection(&mut self) -> anyhow::Result<MailboxStatus>;
+
     /// Optional: Delete multiple emails after processing
     async fn delete_emails(&mut self, ids: &[String]) -> anyhow::Result<()> {
         for id in ids {
@@ -38,14 +65,171 @@ pub trait MailReceiver: Send + Sync {
     }
 }
 
+/// Message counts reported by `MailReceiver::check_connection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxStatus {
+    pub messages: usize,
+    /// Messages without the \Seen flag, for protocols that have flags.
+    pub unseen: Option<usize>,
+}
+
+/// A step of opening a session with a mail server, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectStage {
+    /// Reaching the server, directly or through the proxy, and reading its greeting.
+    Connect,
+    /// The TLS handshake, either implicit or after STARTTLS.
+    Tls,
+    /// Logging in.
+    Auth,
+    /// Opening the mailbox once logged in.
+    Mailbox,
+}
+
+/// Why opening a session with a mail server failed, and at which step.
+#[derive(Debug, Clone)]
+pub struct ConnectError {
+    pub stage: ConnectStage,
+    message: String,
+}
+
+impl ConnectError {
+    pub fn new(stage: ConnectStage, message: impl Into<String>) -> Self {
+        Self {
+            stage,
+            message: message.into(),
+        }
+    }
+
+    /// The step an error failed at, assuming the connection itself if it is untagged.
+    pub fn stage_of(error: &anyhow::Error) -> ConnectStage {
+        error
+            .downcast_ref::<Self>()
+            .map_or(ConnectStage::Connect, |e| e.stage)
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// Why sending an email failed, classified so callers can decide whether to retry.
+#[derive(Debug, Clone)]
+pub struct SendError {
+    /// The SMTP reply code, e.g. 451 or 550, if the server rejected the email.
+    pub code: Option<u16>,
+    /// The enhanced status code (RFC 3463), e.g. "5.1.1", if the reply carried one.
+    pub enhanced_status: Option<String>,
+    /// Whether trying again later may succeed: 4xx replies, connection problems
+    /// and any failure to open the session, including a rejected login.
+    pub transient: bool,
+    message: String,
+}
+
+impl SendError {
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self {
+            code: None,
+            enhanced_status: None,
+            transient: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            transient: false,
+            ..Self::transient(message)
+        }
+    }
+
+    /// Marks the error as transient, keeping its code, e.g. for a failed login
+    /// that will succeed again once the password is fixed.
+    pub fn into_transient(self) -> Self {
+        Self {
+            transient: true,
+            ..self
+        }
+    }
+
+    /// Builds an error from an SMTP reply, classified by its code.
+    pub fn from_reply(code: u16, text: &str, message: impl Into<String>) -> Self {
+        let enhanced_status = text
+            .split_whitespace()
+            .next()
+            .filter(|token| is_enhanced_status(token))
+            .map(str::to_string);
+
+        Self {
+            code: Some(code),
+            enhanced_status,
+            transient: (400..500).contains(&code),
+            message: message.into(),
+        }
+    }
+}
+
+/// Checks for an RFC 3463 status code: class "2", "4" or "5", then two numbers.
+fn is_enhanced_status(token: &str) -> bool {
+    let parts: Vec<&str> = token.split('.').collect();
+    parts.len() == 3
+        && matches!(parts[0], "2" | "4" | "5")
+        && parts[1..]
+            .iter()
+            .all(|part| (1..=3).contains(&part.len()) && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SendError {}
+
 #[async_trait]
 pub trait MailSender: Send + Sync {
-    /// Sends an email content to a specific recipient
-    async fn send_email(&self, email: &Email, target_address: &str) -> anyhow::Result<()>;
+    /// Sends an email content to the given recipients
+    async fn send_email(&self, email: &Email, target_addresses: &[String])
+    -> Result<(), SendError>;
 }
 
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait Notification: Send + Sync {
     /// Sends a notification about a successfully forwarded email
-    async fn notify(&self, email: &Email, target_address: &str) -> anyhow::Result<()>;
+    async fn notify(&self, email: &Email, target_addresses: &[String]) -> anyhow::Result<()>;
+
+    /// Sends a notification about an email that could not be forwarded and was given up on
+    async fn notify_failure(
+        &self,
+        email: &Email,
+        target_addresses: &[String],
+        error: &SendError,
+    ) -> anyhow::Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_error_from_reply() {
+        let error = SendError::from_reply(550, "5.1.1 <nobody@example.com>: User unknown", "");
+        assert_eq!(error.code, Some(550));
+        assert_eq!(error.enhanced_status.as_deref(), Some("5.1.1"));
+        assert!(!error.transient);
+
+        let error = SendError::from_reply(421, "4.3.2 Service shutting down", "");
+        assert_eq!(error.enhanced_status.as_deref(), Some("4.3.2"));
+        assert!(error.transient);
+
+        let error = SendError::from_reply(554, "Transaction failed", "");
+        assert_eq!(error.enhanced_status, None);
+        assert!(!error.transient);
+    }
 }
@@ -0,0 +1,834 @@
+/*!
+ * Copyright (c) 2026 Ming Lyu, aka mingcheng
+ *
+ * This source code is licensed under the MIT License,
+ * which is located in the LICENSE file in the source tree's root directory.
+ *
+ * File: validation.rs
+ * Author: mingcheng <mingcheng@apache.org>
+ * File Created: 2026-10-16 20:30:12
+ *
+ * Modified By: mingcheng <mingcheng@apache.org>
+ * Last Modified: 2026-10-16 20:30:12
+ */
+
+use crate::config::{
+    AppConfig, ArcConfig, DkimConfig, FilterConfig, HeaderMode, NotificationConfig, ReceiverConfig,
+    RuleConfig, SecretCommand, SenderConfig, SpoolConfig, TlsMode,
+};
+use crate::dkim::{ArcSealer, DkimSigner};
+use crate::state_store::StateStore;
+use lettre::Address;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// A problem found in the configuration, tied to the TOML key it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Dotted path of the offending key, e.g. `receivers[1].port`; empty for
+    /// problems with the file as a whole.
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Loads the configuration like [`AppConfig::new`] and [`AppConfig::new_from_file`]
+/// and reads the secret files it refers to, but on failure reports the keys that
+/// do not deserialize instead of a single error without location.
+pub fn load(path: Option<&str>) -> Result<AppConfig, Vec<ConfigIssue>> {
+    let result = match path {
+        Some(path) => AppConfig::new_from_file(path),
+        None => AppConfig::new(),
+    };
+    let error = match result {
+        Ok(mut config) => {
+            let issues = read_secret_files(&mut config);
+            return if issues.is_empty() {
+                Ok(config)
+            } else {
+                Err(issues)
+            };
+        }
+        Err(e) => e,
+    };
+
+    let issues = AppConfig::source(path)
+        .and_then(|source| source.try_deserialize::<Value>())
+        .map(|root| locate(&root))
+        .unwrap_or_default();
+    if issues.is_empty() {
+        return Err(vec![ConfigIssue::new("", error.to_string())]);
+    }
+    Err(issues)
+}
+
+/// Replaces the secrets given as `password_file`, `token_file` or
+/// `smtp_password_file` with the contents of those files, without the trailing
+/// newline secret files usually end with.
+fn read_secret_files(config: &mut AppConfig) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mut read = |key: String, file: &Option<String>, secret: &mut String| {
+        let Some(file) = file else {
+            return;
+        };
+        if !secret.is_empty() {
+            let field = key.rsplit('.').next().unwrap_or_default();
+            let message = format!(
+                "set either {} or {}, not both",
+                field.trim_end_matches("_file"),
+                field
+            );
+            issues.push(ConfigIssue::new(key, message));
+            return;
+        }
+        match std::fs::read_to_string(file) {
+            Ok(contents) => *secret = contents.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => issues.push(ConfigIssue::new(
+                key,
+                format!("failed to read {}: {}", file, e),
+            )),
+        }
+    };
+
+    let sender = &mut config.sender;
+    read(
+        "sender.password_file".to_string(),
+        &sender.password_file,
+        &mut sender.password,
+    );
+    for (i, receiver) in config.receivers.iter_mut().enumerate() {
+        read(
+            format!("receivers[{}].password_file", i),
+            &receiver.password_file,
+            &mut receiver.password,
+        );
+    }
+    for (i, notification) in config.notifications.iter_mut().enumerate() {
+        match notification {
+            NotificationConfig::Telegram {
+                token, token_file, ..
+            } => read(
+                format!("notifications[{}].token_file", i),
+                token_file,
+                token,
+            ),
+            NotificationConfig::Email {
+                smtp_password,
+                smtp_password_file,
+                ..
+            } => read(
+                format!("notifications[{}].smtp_password_file", i),
+                smtp_password_file,
+                smtp_password,
+            ),
+            NotificationConfig::File { .. } => {}
+        }
+    }
+
+    issues
+}
+
+type Check = fn(&Value) -> Result<(), serde_json::Error>;
+
+fn check<T: DeserializeOwned>(value: &Value) -> Result<(), serde_json::Error> {
+    T::deserialize(value).map(|_| ())
+}
+
+/// Finds every innermost section of the raw configuration that fails to
+/// deserialize on its own, and the key within it that is at fault.
+fn locate(root: &Value) -> Vec<ConfigIssue> {
+    // Parents come before their children, so walking backwards checks children first
+    let mut sections: Vec<(String, &Value, Check)> =
+        vec![(String::new(), root, check::<AppConfig>)];
+    if let Some(sender) = root.get("sender") {
+        sections.push(("sender".to_string(), sender, check::<SenderConfig>));
+        if let Some(dkim) = sender.get("dkim") {
+            sections.push(("sender.dkim".to_string(), dkim, check::<DkimConfig>));
+        }
+        if let Some(arc) = sender.get("arc") {
+            sections.push(("sender.arc".to_string(), arc, check::<ArcConfig>));
+        }
+    }
+    if let Some(spool) = root.get("spool") {
+        sections.push(("spool".to_string(), spool, check::<SpoolConfig>));
+    }
+    let array = |name: &str| {
+        root.get(name)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+    };
+    for (i, receiver) in array("receivers").enumerate() {
+        let key = format!("receivers[{}]", i);
+        sections.push((key.clone(), receiver, check::<ReceiverConfig>));
+        for list in ["include", "exclude"] {
+            let filters = receiver.get(list).and_then(Value::as_array).into_iter();
+            for (j, filter) in filters.flatten().enumerate() {
+                let key = format!("{}.{}[{}]", key, list, j);
+                sections.push((key, filter, check::<FilterConfig>));
+            }
+        }
+    }
+    for (i, notification) in array("notifications").enumerate() {
+        let key = format!("notifications[{}]", i);
+        sections.push((key, notification, check::<NotificationConfig>));
+    }
+    for (i, rule) in array("rules").enumerate() {
+        sections.push((format!("rules[{}]", i), rule, check::<RuleConfig>));
+    }
+
+    let mut issues: Vec<ConfigIssue> = Vec::new();
+    for (key, value, check) in sections.into_iter().rev() {
+        let contains_issue = issues.iter().any(|issue| {
+            key.is_empty()
+                || issue
+                    .key
+                    .strip_prefix(&key)
+                    .is_some_and(|rest| rest.starts_with(['.', '[']))
+        });
+        if contains_issue {
+            continue;
+        }
+        if let Err(error) = check(value) {
+            issues.push(field_issue(&key, value, check, &error.to_string()));
+        }
+    }
+    issues.reverse();
+    issues
+}
+
+/// Pins a deserialization error of a section down to one of its keys.
+fn field_issue(section: &str, value: &Value, check: Check, error: &str) -> ConfigIssue {
+    let join = |field: &str| {
+        if section.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", section, field)
+        }
+    };
+
+    if let Some(field) = error
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.split_once('`'))
+        .map(|(field, _)| field)
+    {
+        return ConfigIssue::new(join(field), "is required");
+    }
+
+    // Fields are visited in order and the first bad value aborts, so the culprit
+    // is the only field whose removal changes the error
+    if let Some(fields) = value.as_object() {
+        for field in fields.keys() {
+            let mut without = fields.clone();
+            without.remove(field);
+            let changed = match check(&Value::Object(without)) {
+                Ok(()) => true,
+                Err(e) => e.to_string() != error,
+            };
+            if changed {
+                return ConfigIssue::new(join(field), error);
+            }
+        }
+    }
+    ConfigIssue::new(section, error)
+}
+
+/// Checks a deserialized configuration for values that would only fail at
+/// runtime: protocols, ports, addresses, file paths and duplicate receivers.
+pub fn validate(config: &AppConfig) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+
+    check_addresses(&mut issues, "forward_to", &config.forward_to);
+    if let Some(log_file) = &config.log_file {
+        check_parent_dir(&mut issues, "log_file", log_file);
+    }
+    if let Some(state_dir) = &config.state_dir {
+        check_dir(&mut issues, "state_dir", state_dir);
+    }
+    if let Some(spool) = &config.spool {
+        check_dir(&mut issues, "spool.dir", &spool.dir);
+        if spool.max_attempts == Some(0) {
+            issues.push(ConfigIssue::new("spool.max_attempts", "must be at least 1"));
+        }
+    }
+
+    validate_sender(&mut issues, &config.sender);
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, receiver) in config.receivers.iter().enumerate() {
+        let key = format!("receivers[{}]", i);
+        validate_receiver(&mut issues, &key, receiver);
+
+        if receiver.forward_to(&config.forward_to).is_empty() && config.rules.is_empty() {
+            issues.push(ConfigIssue::new(
+                format!("{}.forward_to", key),
+                "no destination: set it here, set the global forward_to or add rules",
+            ));
+        }
+
+        // Receivers sharing a state file would forward the same emails twice
+        let state_key = StateStore::key(receiver).to_lowercase();
+        match seen.get(&state_key) {
+            Some(first) => issues.push(ConfigIssue::new(
+                key,
+                format!(
+                    "duplicate of receivers[{}] (same host, username and imap_folder)",
+                    first
+                ),
+            )),
+            None => {
+                seen.insert(state_key, i);
+            }
+        }
+    }
+
+    for (i, notification) in config.notifications.iter().enumerate() {
+        let key = format!("notifications[{}]", i);
+        match notification {
+            NotificationConfig::Telegram {
+                chat_id,
+                token,
+                token_command,
+                ..
+            } => {
+                check_not_empty(&mut issues, &format!("{}.chat_id", key), chat_id);
+                check_secret(
+                    &mut issues,
+                    &format!("{}.token", key),
+                    token,
+                    token_command.as_ref(),
+                );
+            }
+            NotificationConfig::File { file_path } => {
+                check_parent_dir(&mut issues, &format!("{}.file_path", key), file_path);
+            }
+            NotificationConfig::Email {
+                smtp_host,
+                smtp_port,
+                smtp_username,
+                smtp_password,
+                smtp_password_command,
+                ..
+            } => {
+                check_not_empty(&mut issues, &format!("{}.smtp_host", key), smtp_host);
+                check_secret(
+                    &mut issues,
+                    &format!("{}.smtp_password", key),
+                    smtp_password,
+                    smtp_password_command.as_ref(),
+                );
+                check_port(&mut issues, &format!("{}.smtp_port", key), *smtp_port);
+                // Notifications are sent from and to this address
+                check_address(
+                    &mut issues,
+                    &format!("{}.smtp_username", key),
+                    smtp_username,
+                );
+            }
+        }
+    }
+
+    for (i, rule) in config.rules.iter().enumerate() {
+        if let Some(forward_to) = &rule.forward_to {
+            check_addresses(&mut issues, &format!("rules[{}].forward_to", i), forward_to);
+        }
+    }
+
+    issues
+}
+
+fn validate_sender(issues: &mut Vec<ConfigIssue>, sender: &SenderConfig) {
+    check_not_empty(issues, "sender.host", &sender.host);
+    check_port(issues, "sender.port", sender.port);
+    check_not_empty(issues, "sender.username", &sender.username);
+    check_secret(
+        issues,
+        "sender.password",
+        &sender.password,
+        sender.password_command.as_ref(),
+    );
+    if let Some(proxy) = &sender.proxy {
+        check_proxy(issues, "sender.proxy", proxy);
+    }
+    if let Some(envelope_from) = &sender.envelope_from {
+        check_address(issues, "sender.envelope_from", envelope_from);
+    }
+    if sender.srs_secret.is_some() && sender.srs_domain().is_none() {
+        issues.push(ConfigIssue::new(
+            "sender.srs_domain",
+            "SRS needs srs_domain or an envelope_from address with a domain",
+        ));
+    }
+    // The login username is no address to put in Resent-From
+    if sender.header_mode == Some(HeaderMode::Resent) && sender.envelope_from.is_none() {
+        issues.push(ConfigIssue::new(
+            "sender.envelope_from",
+            "header_mode = \"resent\" needs an envelope_from address for Resent-From",
+        ));
+    }
+    if let Some(dkim) = &sender.dkim
+        && let Err(e) = DkimSigner::new(dkim)
+    {
+        issues.push(ConfigIssue::new(
+            "sender.dkim.private_key_file",
+            e.to_string(),
+        ));
+    }
+    if let Some(arc) = &sender.arc
+        && let Err(e) = ArcSealer::new(arc, sender.dkim.as_ref())
+    {
+        issues.push(ConfigIssue::new("sender.arc", e.to_string()));
+    }
+}
+
+fn validate_receiver(issues: &mut Vec<ConfigIssue>, key: &str, receiver: &ReceiverConfig) {
+    let field = |name: &str| format!("{}.{}", key, name);
+
+    check_not_empty(issues, &field("host"), &receiver.host);
+    check_port(issues, &field("port"), receiver.port);
+    check_not_empty(issues, &field("username"), &receiver.username);
+    check_secret(
+        issues,
+        &field("password"),
+        &receiver.password,
+        receiver.password_command.as_ref(),
+    );
+    match receiver.protocol.as_str() {
+        "imap" => {}
+        "pop3" => {
+            if receiver.tls_mode() == TlsMode::Starttls {
+                issues.push(ConfigIssue::new(
+                    field("tls_mode"),
+                    "STARTTLS is not supported for POP3, use \"implicit\" or \"none\"",
+                ));
+            }
+            if receiver.idle == Some(true) {
+                issues.push(ConfigIssue::new(
+                    field("idle"),
+                    "IDLE is only supported for IMAP",
+                ));
+            }
+        }
+        other => issues.push(ConfigIssue::new(
+            field("protocol"),
+            format!(
+                "unknown protocol \"{}\", expected \"pop3\" or \"imap\"",
+                other
+            ),
+        )),
+    }
+    if let Some(proxy) = &receiver.proxy {
+        check_proxy(issues, &field("proxy"), proxy);
+    }
+    if let Some(forward_to) = &receiver.forward_to {
+        check_addresses(issues, &field("forward_to"), forward_to);
+    }
+    for (list, filters) in [
+        ("include", &receiver.include),
+        ("exclude", &receiver.exclude),
+    ] {
+        for (j, filter) in filters.iter().enumerate() {
+            if let Some(subject) = &filter.subject
+                && let Err(e) = Regex::new(subject)
+            {
+                issues.push(ConfigIssue::new(
+                    format!("{}.{}[{}].subject", key, list, j),
+                    format!("invalid regular expression: {}", e),
+                ));
+            }
+        }
+    }
+}
+
+fn check_not_empty(issues: &mut Vec<ConfigIssue>, key: &str, value: &str) {
+    if value.trim().is_empty() {
+        issues.push(ConfigIssue::new(key, "must not be empty"));
+    }
+}
+
+/// Secrets may also come from a file named by the matching `*_file` key, which
+/// has been read into `value` by now, or from the output of a `*_command`.
+fn check_secret(
+    issues: &mut Vec<ConfigIssue>,
+    key: &str,
+    value: &str,
+    command: Option<&SecretCommand>,
+) {
+    let field = key.rsplit('.').next().unwrap_or_default();
+    match command {
+        Some(command) if command.command.is_empty() => {
+            issues.push(ConfigIssue::new(
+                format!("{}_command", key),
+                "must not be empty",
+            ));
+        }
+        Some(_) if !value.is_empty() => issues.push(ConfigIssue::new(
+            format!("{}_command", key),
+            format!("set only one of {0}, {0}_file and {0}_command", field),
+        )),
+        Some(_) => {}
+        None if value.is_empty() => issues.push(ConfigIssue::new(
+            key,
+            format!("is required, set it, {0}_file or {0}_command", field),
+        )),
+        None => {}
+    }
+}
+
+fn check_port(issues: &mut Vec<ConfigIssue>, key: &str, port: u16) {
+    if port == 0 {
+        issues.push(ConfigIssue::new(key, "must be between 1 and 65535"));
+    }
+}
+
+fn check_address(issues: &mut Vec<ConfigIssue>, key: &str, address: &str) {
+    if let Err(e) = address.parse::<Address>() {
+        issues.push(ConfigIssue::new(
+            key,
+            format!("invalid email address \"{}\": {}", address, e),
+        ));
+    }
+}
+
+fn check_addresses(issues: &mut Vec<ConfigIssue>, key: &str, addresses: &[String]) {
+    for address in addresses {
+        check_address(issues, key, address);
+    }
+}
+
+/// SOCKS5 proxies are given as `host:port`.
+fn check_proxy(issues: &mut Vec<ConfigIssue>, key: &str, proxy: &str) {
+    let valid = proxy
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0));
+    if !valid {
+        issues.push(ConfigIssue::new(
+            key,
+            format!("invalid proxy \"{}\", expected host:port", proxy),
+        ));
+    }
+}
+
+/// Directories are created on startup, so only an existing non-directory is a problem.
+fn check_dir(issues: &mut Vec<ConfigIssue>, key: &str, dir: &str) {
+    let path = Path::new(dir);
+    if path.exists() && !path.is_dir() {
+        issues.push(ConfigIssue::new(key, format!("{} is not a directory", dir)));
+    }
+}
+
+/// Files are created when first written, but not their directory.
+fn check_parent_dir(issues: &mut Vec<ConfigIssue>, key: &str, file: &str) {
+    let path = Path::new(file);
+    if path.is_dir() {
+        issues.push(ConfigIssue::new(key, format!("{} is a directory", file)));
+        return;
+    }
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+        && !parent.is_dir()
+    {
+        issues.push(ConfigIssue::new(
+            key,
+            format!("directory {} does not exist", parent.display()),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+
+    const VALID: &str = r#"
+        forward_to = "target@example.com"
+
+        [sender]
+        host = "smtp.example.com"
+        port = 587
+        username = "sender@example.com"
+        password = "p"
+
+        [[receivers]]
+        host = "pop.example.com"
+        port = 995
+        username = "a@example.com"
+        password = "p"
+
+        [[receivers]]
+        host = "imap.example.com"
+        port = 993
+        username = "a@example.com"
+        password = "p"
+        protocol = "imap"
+    "#;
+
+    fn raw(toml: &str) -> Value {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn parse(toml: &str) -> AppConfig {
+        Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn keys(issues: &[ConfigIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert_eq!(validate(&parse(VALID)), vec![]);
+    }
+
+    #[test]
+    fn test_shipped_example_config_is_valid() {
+        let config = parse(include_str!("../config.toml"));
+        assert_eq!(validate(&config), vec![]);
+    }
+
+    #[test]
+    fn test_locate_missing_field() {
+        let toml = VALID.replacen("host = \"imap.example.com\"", "", 1);
+        let issues = locate(&raw(&toml));
+        assert_eq!(
+            issues,
+            vec![ConfigIssue::new("receivers[1].host", "is required")]
+        );
+    }
+
+    #[test]
+    fn test_locate_bad_values() {
+        let toml = VALID
+            .replace("port = 995", "port = 70000")
+            .replace("port = 587", "port = 587\nheader_mode = \"bogus\"");
+        let issues = locate(&raw(&toml));
+        assert_eq!(keys(&issues), ["sender.header_mode", "receivers[0].port"]);
+        assert!(issues[0].message.contains("unknown variant `bogus`"));
+        assert!(issues[1].message.contains("70000"));
+    }
+
+    #[test]
+    fn test_locate_nested_filter() {
+        let toml = format!(
+            "{}\n{}",
+            VALID,
+            r#"
+            exclude = [{ subject = "x" }, { max_size = "big" }]
+            "#
+        );
+        let issues = locate(&raw(&toml));
+        assert_eq!(keys(&issues), ["receivers[1].exclude[1].max_size"]);
+    }
+
+    #[test]
+    fn test_protocols_ports_and_addresses() {
+        let toml = VALID
+            .replace("protocol = \"imap\"", "protocol = \"imaps\"")
+            .replace(
+                "port = 995",
+                "port = 0\ntls_mode = \"starttls\"\nidle = true",
+            )
+            .replace(
+                "forward_to = \"target@example.com\"",
+                "forward_to = [\"target@example.com\", \"not an address\"]",
+            );
+        let issues = validate(&parse(&toml));
+        assert_eq!(
+            keys(&issues),
+            [
+                "forward_to",
+                "receivers[0].port",
+                "receivers[0].tls_mode",
+                "receivers[0].idle",
+                "receivers[1].protocol",
+            ]
+        );
+        assert!(issues[0].message.contains("not an address"));
+        assert!(issues[4].message.contains("\"imaps\""));
+    }
+
+    #[test]
+    fn test_duplicate_receivers() {
+        let toml = VALID.replace("imap.example.com", "POP.example.com");
+        let issues = validate(&parse(&toml));
+        assert_eq!(keys(&issues), ["receivers[1]"]);
+        assert!(issues[0].message.contains("receivers[0]"));
+    }
+
+    #[test]
+    fn test_file_paths_and_sender() {
+        let toml = format!(
+            "log_file = \"/nonexistent/dir/forwarder.log\"\n{}\n{}",
+            VALID.replacen(
+                "password = \"p\"\n",
+                "password = \"p\"\nproxy = \"localhost\"\nsrs_secret = \"s\"\n\
+                 dkim = { domain = \"example.com\", selector = \"s\", private_key_file = \"/nonexistent.pem\" }\n",
+                1,
+            ),
+            r#"
+            [[notifications]]
+            type = "file"
+            file_path = "/nonexistent/dir/notifications.txt"
+            "#
+        );
+        let mut config = parse(&toml);
+        config.sender.username = "no-domain".to_string();
+        config.sender.header_mode = Some(HeaderMode::Resent);
+        let issues = validate(&config);
+        assert_eq!(
+            keys(&issues),
+            [
+                "log_file",
+                "sender.proxy",
+                "sender.srs_domain",
+                "sender.envelope_from",
+                "sender.dkim.private_key_file",
+                "notifications[0].file_path",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_secret_files() {
+        let dir =
+            std::env::temp_dir().join(format!("mail-forwarder-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("password");
+        std::fs::write(&secret, "s3cret\n").unwrap();
+        let password_file = format!("password_file = \"{}\"\n", secret.display());
+
+        let toml = VALID
+            .replacen("password = \"p\"\n", &password_file, 1)
+            .replacen(
+                "password = \"p\"\n",
+                "password_file = \"/nonexistent/password\"\n",
+                1,
+            )
+            .replacen(
+                "password = \"p\"\n",
+                &format!("password = \"p\"\n{}", password_file),
+                1,
+            )
+            + &format!(
+                "[[notifications]]\ntype = \"telegram\"\nchat_id = \"1\"\ntoken_file = \"{}\"\n",
+                secret.display()
+            );
+        let mut config = parse(&toml);
+        let issues = read_secret_files(&mut config);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            keys(&issues),
+            ["receivers[0].password_file", "receivers[1].password_file"]
+        );
+        assert!(
+            issues[0]
+                .message
+                .starts_with("failed to read /nonexistent/password")
+        );
+        assert_eq!(
+            issues[1].message,
+            "set either password or password_file, not both"
+        );
+        assert_eq!(config.sender.password, "s3cret");
+        assert!(matches!(
+            &config.notifications[0],
+            NotificationConfig::Telegram { token, .. } if token == "s3cret"
+        ));
+        assert_eq!(keys(&validate(&config)), ["receivers[0].password"]);
+    }
+
+    #[test]
+    fn test_secret_commands() {
+        let toml = VALID
+            .replacen(
+                "password = \"p\"\n",
+                "password_command = [\"pass\", \"show\", \"mail/sender\"]\n",
+                1,
+            )
+            .replacen(
+                "password = \"p\"\n",
+                "password = \"q\"\npassword_command = [\"pass\", \"show\", \"mail/pop\"]\n",
+                1,
+            )
+            .replacen(
+                "password = \"p\"\n",
+                "password_command = { command = [] }\n",
+                1,
+            )
+            + "[[notifications]]\ntype = \"telegram\"\nchat_id = \"1\"\n\
+               token_command = { command = [\"vault\", \"read\", \"bot\"], ttl_seconds = 60 }\n";
+        let config = parse(&toml);
+
+        assert_eq!(
+            config.sender.password_command,
+            Some(SecretCommand {
+                command: vec!["pass".into(), "show".into(), "mail/sender".into()],
+                ttl_seconds: None,
+            })
+        );
+        assert!(matches!(
+            &config.notifications[0],
+            NotificationConfig::Telegram { token_command: Some(command), .. }
+                if command.ttl() == std::time::Duration::from_secs(60)
+        ));
+
+        let issues = validate(&config);
+        assert_eq!(
+            keys(&issues),
+            [
+                "receivers[0].password_command",
+                "receivers[1].password_command"
+            ]
+        );
+        assert_eq!(
+            issues[0].message,
+            "set only one of password, password_file and password_command"
+        );
+        assert_eq!(issues[1].message, "must not be empty");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            ConfigIssue::new("receivers[0].port", "must be between 1 and 65535").to_string(),
+            "receivers[0].port: must be between 1 and 65535"
+        );
+        assert_eq!(
+            ConfigIssue::new("", "file not found").to_string(),
+            "file not found"
+        );
+    }
+}